@@ -0,0 +1,318 @@
+// SPDX-License-Identifier: MIT
+
+/// System76 EC command tool - speaks the firmware's shared memory command protocol through the
+/// H2RAM windows of a running ecsim
+
+use ecsim::client::{Client, ClientError};
+use std::{env, fmt, fs, process};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Command region of the H2RAM window, as host I/O ports
+const CMD_BASE: u16 = 0xE00;
+const CMD_RES: u16 = CMD_BASE + 1;
+const CMD_DATA: u16 = CMD_BASE + 2;
+const CMD_DATA_SIZE: usize = 0x100 - 2;
+
+const CMD_PROBE: u8 = 1;
+const CMD_BOARD: u8 = 2;
+const CMD_VERSION: u8 = 3;
+const CMD_PRINT: u8 = 4;
+const CMD_SPI: u8 = 5;
+const CMD_RESET: u8 = 6;
+const CMD_FAN_GET: u8 = 7;
+const CMD_FAN_SET: u8 = 8;
+const CMD_KEYMAP_GET: u8 = 9;
+const CMD_KEYMAP_SET: u8 = 10;
+const CMD_LED_GET_VALUE: u8 = 11;
+const CMD_LED_SET_VALUE: u8 = 12;
+const CMD_LED_GET_COLOR: u8 = 13;
+const CMD_LED_SET_COLOR: u8 = 14;
+const CMD_LED_GET_MODE: u8 = 15;
+const CMD_LED_SET_MODE: u8 = 16;
+
+const RES_OK: u8 = 0;
+
+const SPI_READ: u8 = 1 << 0;
+const SPI_DISABLE: u8 = 1 << 1;
+const SPI_SCRATCH: u8 = 1 << 2;
+
+enum Error {
+    Client(ClientError),
+    /// Firmware responded with an error result
+    Result(u8),
+    /// Probe found no System76 EC
+    Signature(u8, u8),
+    /// Response did not match the request
+    Verify,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Client(err) => write!(f, "{}", err),
+            Error::Result(res) => write!(f, "command failed with result {}", res),
+            Error::Signature(a, b) => write!(f, "unknown signature {:02X}{:02X}", a, b),
+            Error::Verify => write!(f, "verification failed"),
+        }
+    }
+}
+
+impl From<ClientError> for Error {
+    fn from(err: ClientError) -> Self {
+        Error::Client(err)
+    }
+}
+
+struct Ec {
+    client: Client<TcpStream>,
+}
+
+impl Ec {
+    /// Run a command, with data sent before and read back after
+    fn command(&mut self, cmd: u8, data: &mut [u8]) -> Result<(), Error> {
+        // Wait for the previous command to finish
+        self.client.wait(CMD_BASE, 0xFF, 0)?;
+        self.client.h2ram_write(CMD_DATA, data)?;
+        self.client.outb(CMD_BASE, cmd)?;
+        self.client.wait(CMD_BASE, 0xFF, 0)?;
+
+        let res = self.client.inb(CMD_RES)?;
+        if res != RES_OK {
+            return Err(Error::Result(res));
+        }
+        self.client.h2ram_read(CMD_DATA, data)?;
+        Ok(())
+    }
+
+    fn probe(&mut self) -> Result<u8, Error> {
+        let mut data = [0; 3];
+        self.command(CMD_PROBE, &mut data)?;
+        match data {
+            [0x76, 0xEC, version] => Ok(version),
+            [a, b, _] => Err(Error::Signature(a, b)),
+        }
+    }
+
+    fn string(&mut self, cmd: u8) -> Result<String, Error> {
+        let mut data = [0; CMD_DATA_SIZE];
+        self.command(cmd, &mut data)?;
+        let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        Ok(String::from_utf8_lossy(&data[..len]).into_owned())
+    }
+
+    fn print(&mut self, text: &[u8]) -> Result<(), Error> {
+        for chunk in text.chunks(CMD_DATA_SIZE - 2) {
+            let mut data = vec![0; chunk.len() + 2];
+            data[1] = chunk.len() as u8;
+            data[2..].copy_from_slice(chunk);
+            self.command(CMD_PRINT, &mut data)?;
+            if data[1] != chunk.len() as u8 {
+                return Err(Error::Verify);
+            }
+        }
+        Ok(())
+    }
+
+    /// One SPI transaction chunk, through the firmware's scratch ROM
+    fn spi(&mut self, flags: u8, data: &mut [u8]) -> Result<(), Error> {
+        for chunk in data.chunks_mut(CMD_DATA_SIZE - 2) {
+            let mut buf = vec![0; chunk.len() + 2];
+            buf[0] = flags | SPI_SCRATCH;
+            buf[1] = chunk.len() as u8;
+            buf[2..].copy_from_slice(chunk);
+            self.command(CMD_SPI, &mut buf)?;
+            if buf[1] != chunk.len() as u8 {
+                return Err(Error::Verify);
+            }
+            chunk.copy_from_slice(&buf[2..]);
+        }
+        Ok(())
+    }
+
+    /// Send an SPI command and deselect the flash
+    fn spi_command(&mut self, command: &[u8]) -> Result<(), Error> {
+        let mut command = command.to_vec();
+        self.spi(SPI_DISABLE, &mut command)
+    }
+
+    fn spi_status(&mut self) -> Result<u8, Error> {
+        self.spi(0, &mut [0x05])?;
+        let mut status = [0];
+        self.spi(SPI_READ | SPI_DISABLE, &mut status)?;
+        Ok(status[0])
+    }
+
+    fn spi_wait(&mut self) -> Result<(), Error> {
+        while self.spi_status()? & 1 != 0 {}
+        Ok(())
+    }
+
+    fn flash(&mut self, image: &[u8]) -> Result<(), Error> {
+        // The internal flash erases 1 KiB sectors with 0xD7
+        for (i, sector) in image.chunks(1024).enumerate() {
+            let a = ((i * 1024) as u32).to_be_bytes();
+            self.spi_command(&[0x06])?;
+            self.spi_command(&[0xD7, a[1], a[2], a[3]])?;
+            self.spi_wait()?;
+
+            for (j, page) in sector.chunks(256).enumerate() {
+                let addr = (i * 1024 + j * 256) as u32;
+                let a = addr.to_be_bytes();
+
+                // Program page with AAI, two bytes at a time
+                self.spi_command(&[0x06])?;
+                for (k, word) in page.chunks(2).enumerate() {
+                    let d0 = word[0];
+                    let d1 = word.get(1).copied().unwrap_or(0xFF);
+                    if k == 0 {
+                        self.spi_command(&[0xAD, a[1], a[2], a[3], d0, d1])?;
+                    } else {
+                        self.spi_command(&[0xAD, d0, d1])?;
+                    }
+                    self.spi_wait()?;
+                }
+                self.spi_command(&[0x04])?;
+                self.spi_wait()?;
+
+                // Verify page
+                self.spi(0, &mut [0x0B, a[1], a[2], a[3], 0])?;
+                let mut read = vec![0; page.len()];
+                self.spi(SPI_READ | SPI_DISABLE, &mut read)?;
+                if read != page {
+                    return Err(Error::Verify);
+                }
+            }
+
+            eprint!("\rflashed {} KiB", i + 1);
+        }
+        eprintln!();
+        Ok(())
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("ectool [--host ADDR] COMMAND");
+    eprintln!("  info");
+    eprintln!("  print TEXT");
+    eprintln!("  fan get INDEX | fan set INDEX DUTY");
+    eprintln!("  keymap get LAYER OUTPUT INPUT | keymap set LAYER OUTPUT INPUT VALUE");
+    eprintln!("  led get-value INDEX | led set-value INDEX VALUE");
+    eprintln!("  led get-color INDEX | led set-color INDEX R G B");
+    eprintln!("  led get-mode LAYER | led set-mode LAYER MODE SPEED");
+    eprintln!("  reset");
+    eprintln!("  flash FILE");
+    process::exit(1);
+}
+
+fn parse<T: std::str::FromStr>(arg: &str) -> T {
+    match arg.parse() {
+        Ok(ok) => ok,
+        Err(_) => {
+            eprintln!("invalid number '{}'", arg);
+            usage();
+        }
+    }
+}
+
+fn run(ec: &mut Ec, args: &[String]) -> Result<(), Error> {
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    match args.as_slice() {
+        ["info"] => {
+            println!("protocol: {}", ec.probe()?);
+            println!("board: {}", ec.string(CMD_BOARD)?);
+            println!("version: {}", ec.string(CMD_VERSION)?);
+        },
+        ["print", text @ ..] => {
+            let mut text = text.join(" ");
+            text.push('\n');
+            ec.print(text.as_bytes())?;
+        },
+        ["fan", "get", index] => {
+            let mut data = [parse(index), 0];
+            ec.command(CMD_FAN_GET, &mut data)?;
+            println!("{}", data[1]);
+        },
+        ["fan", "set", index, duty] => {
+            ec.command(CMD_FAN_SET, &mut [parse(index), parse(duty)])?;
+        },
+        ["keymap", "get", layer, output, input] => {
+            let mut data = [parse(layer), parse(output), parse(input), 0, 0];
+            ec.command(CMD_KEYMAP_GET, &mut data)?;
+            println!("{:04X}", u16::from_le_bytes([data[3], data[4]]));
+        },
+        ["keymap", "set", layer, output, input, value] => {
+            let value = match u16::from_str_radix(value, 16) {
+                Ok(ok) => ok.to_le_bytes(),
+                Err(_) => usage(),
+            };
+            let mut data = [parse(layer), parse(output), parse(input), value[0], value[1]];
+            ec.command(CMD_KEYMAP_SET, &mut data)?;
+        },
+        ["led", "get-value", index] => {
+            let mut data = [parse(index), 0, 0];
+            ec.command(CMD_LED_GET_VALUE, &mut data)?;
+            println!("{} / {}", data[1], data[2]);
+        },
+        ["led", "set-value", index, value] => {
+            ec.command(CMD_LED_SET_VALUE, &mut [parse(index), parse(value)])?;
+        },
+        ["led", "get-color", index] => {
+            let mut data = [parse(index), 0, 0, 0];
+            ec.command(CMD_LED_GET_COLOR, &mut data)?;
+            println!("{} {} {}", data[1], data[2], data[3]);
+        },
+        ["led", "set-color", index, r, g, b] => {
+            ec.command(CMD_LED_SET_COLOR, &mut [parse(index), parse(r), parse(g), parse(b)])?;
+        },
+        ["led", "get-mode", layer] => {
+            let mut data = [parse(layer), 0, 0];
+            ec.command(CMD_LED_GET_MODE, &mut data)?;
+            println!("mode {} speed {}", data[1], data[2]);
+        },
+        ["led", "set-mode", layer, mode, speed] => {
+            ec.command(CMD_LED_SET_MODE, &mut [parse(layer), parse(mode), parse(speed)])?;
+        },
+        ["reset"] => {
+            ec.command(CMD_RESET, &mut [])?;
+        },
+        ["flash", path] => {
+            let image = match fs::read(path) {
+                Ok(ok) => ok,
+                Err(err) => {
+                    eprintln!("failed to read {}: {}", path, err);
+                    process::exit(1);
+                }
+            };
+            ec.flash(&image)?;
+        },
+        _ => usage(),
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut host = "127.0.0.1:8588".to_string();
+    if args.first().map(|arg| arg.as_str()) == Some("--host") {
+        if args.len() < 2 {
+            usage();
+        }
+        host = args.remove(1);
+        args.remove(0);
+    }
+
+    let client = match Client::connect_tcp(&host, Duration::from_secs(5)) {
+        Ok(ok) => ok,
+        Err(err) => {
+            eprintln!("failed to connect to {}: {}", host, err);
+            process::exit(1);
+        }
+    };
+    let mut ec = Ec { client };
+
+    if let Err(err) = run(&mut ec, &args) {
+        eprintln!("ectool: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,409 @@
+// SPDX-License-Identifier: MIT
+
+//! Host client for the framed protocol, with helpers for the PMC, ACPI EC, KBC, and H2RAM
+//! interfaces of the simulated EC.
+
+use std::{fmt, io};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::host::Notification;
+use crate::kbc;
+use crate::pmc;
+use crate::protocol::{self, Frame};
+
+/// Error from a host client request
+#[derive(Debug)]
+pub enum ClientError {
+    /// Connection failed
+    Io(io::Error),
+    /// The simulator did not respond, or a wait did not complete, in time
+    Timeout,
+    /// The simulator responded with an error status
+    Status(u8),
+    /// The response was not valid
+    Protocol,
+    /// An earlier request failed to send or receive, which can leave the stream in the middle of
+    /// a frame, so the client has to connect again
+    Broken,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Io(err) => write!(f, "connection failed: {}", err),
+            ClientError::Timeout => write!(f, "timed out"),
+            ClientError::Status(status) => write!(f, "request failed with status 0x{:02X}", status),
+            ClientError::Protocol => write!(f, "invalid response"),
+            ClientError::Broken => write!(f, "connection unusable after an earlier failure"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ClientError::Timeout,
+            _ => ClientError::Io(err),
+        }
+    }
+}
+
+/// Bytes of data in each memory cycle request, the most MEMR can return
+const MEM_CHUNK: usize = 255;
+
+/// Ports in each batch of H2RAM accesses, more than the largest window while keeping requests and
+/// responses well within a frame
+const H2RAM_CHUNK: usize = 4096;
+
+/// Parse the payload of a notification frame
+pub fn parse_notification(payload: &[u8]) -> Option<Notification> {
+    match payload {
+        [protocol::NOTIFY_IRQ, irq] => Some(Notification::Irq(*irq)),
+        [protocol::NOTIFY_SCI, _] => Some(Notification::Sci),
+        [protocol::NOTIFY_SMI, _] => Some(Notification::Smi),
+        [protocol::NOTIFY_VW, index, value] => Some(Notification::VirtualWire(*index, *value)),
+        _ => None,
+    }
+}
+
+/// Connection to a simulator serving the framed protocol
+pub struct Client<S: Read + Write> {
+    stream: S,
+    next_id: u16,
+    /// Steps to wait for status bits before failing with `ClientError::Timeout`
+    pub timeout_steps: u32,
+    notifications: Vec<Notification>,
+    /// Set when sending or receiving failed, after which every request fails
+    broken: bool,
+}
+
+impl Client<TcpStream> {
+    /// Connect over TCP. Requests fail with `ClientError::Timeout` if no response arrives in time
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A, timeout: Duration) -> Result<Self, ClientError> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(timeout))?;
+        Client::new(stream)
+    }
+}
+
+#[cfg(unix)]
+impl Client<std::os::unix::net::UnixStream> {
+    /// Connect over a Unix socket. Requests fail with `ClientError::Timeout` if no response
+    /// arrives in time
+    pub fn connect_unix<P: AsRef<std::path::Path>>(path: P, timeout: Duration) -> Result<Self, ClientError> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(timeout))?;
+        Client::new(stream)
+    }
+}
+
+impl<S: Read + Write> Client<S> {
+    /// Use a connected stream, checking the protocol version
+    pub fn new(stream: S) -> Result<Self, ClientError> {
+        let mut client = Self {
+            stream,
+            next_id: 1,
+            timeout_steps: 10_000_000,
+            notifications: Vec::new(),
+            broken: false,
+        };
+        match client.request(protocol::HELLO, &[])?.as_slice() {
+            [protocol::VERSION] => Ok(client),
+            _ => Err(ClientError::Protocol),
+        }
+    }
+
+    /// Check if an earlier request failed to send or receive, so that the client has to connect
+    /// again
+    pub fn broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Send a request and return the data of its response. Notifications received while waiting
+    /// are kept for `notifications`. If sending or receiving fails, including timeouts, the
+    /// stream may be in the middle of a frame, so this and all later requests fail with
+    /// `ClientError::Broken` after the first error
+    pub fn request(&mut self, kind: u8, payload: &[u8]) -> Result<Vec<u8>, ClientError> {
+        if self.broken {
+            return Err(ClientError::Broken);
+        }
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let request = Frame {
+            version: protocol::VERSION,
+            kind,
+            id,
+            payload: payload.to_vec(),
+        };
+        // Oversized requests fail before anything is sent
+        let bytes = request.to_bytes()?;
+
+        let frame = match self.exchange(&bytes, kind, id) {
+            Ok(ok) => ok,
+            Err(err) => {
+                self.broken = true;
+                return Err(err.into());
+            }
+        };
+        match frame.payload.split_first() {
+            Some((&protocol::STATUS_OK, data)) => Ok(data.to_vec()),
+            Some((&protocol::STATUS_TIMEOUT, _)) => Err(ClientError::Timeout),
+            Some((&status, _)) => Err(ClientError::Status(status)),
+            None => Err(ClientError::Protocol),
+        }
+    }
+
+    /// Send a request and read frames until its response
+    fn exchange(&mut self, bytes: &[u8], kind: u8, id: u16) -> io::Result<Frame> {
+        self.stream.write_all(bytes)?;
+        loop {
+            let frame = Frame::read(&mut self.stream)?;
+            if frame.kind == protocol::NOTIFY {
+                if let Some(notification) = parse_notification(&frame.payload) {
+                    self.notifications.push(notification);
+                }
+            } else if frame.kind == kind | protocol::RESPONSE && frame.id == id {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Take the notifications received since the last call
+    pub fn notifications(&mut self) -> Vec<Notification> {
+        self.notifications.drain(..).collect()
+    }
+
+    pub fn inb(&mut self, port: u16) -> Result<u8, ClientError> {
+        match self.request(protocol::INB, &port.to_le_bytes())?.as_slice() {
+            [value] => Ok(*value),
+            _ => Err(ClientError::Protocol),
+        }
+    }
+
+    pub fn outb(&mut self, port: u16, value: u8) -> Result<(), ClientError> {
+        let port = port.to_le_bytes();
+        self.request(protocol::OUTB, &[port[0], port[1], value])?;
+        Ok(())
+    }
+
+    /// Execute a batch of operations, given as kind and payload, in one request, returning the
+    /// data of each operation
+    pub fn batch(&mut self, ops: &[(u8, Vec<u8>)]) -> Result<Vec<Vec<u8>>, ClientError> {
+        let mut payload = Vec::new();
+        for (kind, op) in ops.iter() {
+            if op.len() > u8::MAX as usize {
+                return Err(ClientError::Protocol);
+            }
+            payload.push(*kind);
+            payload.push(op.len() as u8);
+            payload.extend_from_slice(op);
+        }
+
+        let response = self.request(protocol::BATCH, &payload)?;
+        let mut results = Vec::with_capacity(ops.len());
+        let mut remaining = response.as_slice();
+        while let [status, length, rest @ ..] = remaining {
+            if *status != protocol::STATUS_OK {
+                return Err(ClientError::Status(*status));
+            }
+            let length = *length as usize;
+            let data = rest.get(..length).ok_or(ClientError::Protocol)?;
+            results.push(data.to_vec());
+            remaining = &rest[length..];
+        }
+        if results.len() != ops.len() {
+            return Err(ClientError::Protocol);
+        }
+        Ok(results)
+    }
+
+    /// Read memory with memory cycles
+    pub fn read_mem(&mut self, address: u32, data: &mut [u8]) -> Result<(), ClientError> {
+        for (i, chunk) in data.chunks_mut(MEM_CHUNK).enumerate() {
+            let mut payload = address.wrapping_add((i * MEM_CHUNK) as u32).to_le_bytes().to_vec();
+            payload.push(chunk.len() as u8);
+            let response = self.request(protocol::MEMR, &payload)?;
+            if response.len() != chunk.len() {
+                return Err(ClientError::Protocol);
+            }
+            chunk.copy_from_slice(&response);
+        }
+        Ok(())
+    }
+
+    /// Write memory with memory cycles
+    pub fn write_mem(&mut self, address: u32, data: &[u8]) -> Result<(), ClientError> {
+        for (i, chunk) in data.chunks(MEM_CHUNK).enumerate() {
+            let mut payload = address.wrapping_add((i * MEM_CHUNK) as u32).to_le_bytes().to_vec();
+            payload.extend_from_slice(chunk);
+            self.request(protocol::MEMW, &payload)?;
+        }
+        Ok(())
+    }
+
+    /// Wait for a port read masked to equal a value, returning the value read
+    pub fn wait(&mut self, port: u16, mask: u8, value: u8) -> Result<u8, ClientError> {
+        let mut payload = port.to_le_bytes().to_vec();
+        payload.push(mask);
+        payload.push(value);
+        payload.extend_from_slice(&self.timeout_steps.to_le_bytes());
+        match self.request(protocol::WAIT, &payload)?.as_slice() {
+            [value] => Ok(*value),
+            _ => Err(ClientError::Protocol),
+        }
+    }
+
+    /// Read I/O ports of an H2RAM window, starting at a port, with one batch request
+    pub fn h2ram_read(&mut self, port: u16, data: &mut [u8]) -> Result<(), ClientError> {
+        for (i, chunk) in data.chunks_mut(H2RAM_CHUNK).enumerate() {
+            let start = port.wrapping_add((i * H2RAM_CHUNK) as u16);
+            let ops: Vec<(u8, Vec<u8>)> = (0..chunk.len())
+                .map(|j| (protocol::INB, start.wrapping_add(j as u16).to_le_bytes().to_vec()))
+                .collect();
+            for (value, result) in chunk.iter_mut().zip(self.batch(&ops)?) {
+                *value = match result.as_slice() {
+                    [value] => *value,
+                    _ => return Err(ClientError::Protocol),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Write I/O ports of an H2RAM window, starting at a port, with one batch request
+    pub fn h2ram_write(&mut self, port: u16, data: &[u8]) -> Result<(), ClientError> {
+        for (i, chunk) in data.chunks(H2RAM_CHUNK).enumerate() {
+            let start = port.wrapping_add((i * H2RAM_CHUNK) as u16);
+            let ops: Vec<(u8, Vec<u8>)> = chunk.iter()
+                .enumerate()
+                .map(|(j, value)| {
+                    let port = start.wrapping_add(j as u16).to_le_bytes();
+                    (protocol::OUTB, vec![port[0], port[1], *value])
+                })
+                .collect();
+            self.batch(&ops)?;
+        }
+        Ok(())
+    }
+}
+
+/// ACPI EC status bit set while burst mode is enabled
+pub const ACPI_STATUS_BURST: u8 = 1 << 4;
+/// ACPI EC status bit set while an event is waiting for QR_EC
+pub const ACPI_STATUS_SCI_EVT: u8 = 1 << 5;
+
+/// PMC channel, as seen from the host
+#[derive(Clone, Copy, Debug)]
+pub struct Pmc {
+    pub data: u16,
+    pub command: u16,
+}
+
+impl Pmc {
+    /// PMC1, used for ACPI
+    pub const ACPI: Pmc = Pmc::new(0x62, 0x66);
+
+    pub const fn new(data: u16, command: u16) -> Self {
+        Self { data, command }
+    }
+
+    pub fn status<S: Read + Write>(&self, client: &mut Client<S>) -> Result<u8, ClientError> {
+        client.inb(self.command)
+    }
+
+    /// Send a command once the input buffer is empty
+    pub fn command<S: Read + Write>(&self, client: &mut Client<S>, value: u8) -> Result<(), ClientError> {
+        client.wait(self.command, pmc::STATUS_IBF, 0)?;
+        client.outb(self.command, value)
+    }
+
+    /// Send data once the input buffer is empty
+    pub fn write<S: Read + Write>(&self, client: &mut Client<S>, value: u8) -> Result<(), ClientError> {
+        client.wait(self.command, pmc::STATUS_IBF, 0)?;
+        client.outb(self.data, value)
+    }
+
+    /// Read data once the output buffer is full
+    pub fn read<S: Read + Write>(&self, client: &mut Client<S>) -> Result<u8, ClientError> {
+        client.wait(self.command, pmc::STATUS_OBF, pmc::STATUS_OBF)?;
+        client.inb(self.data)
+    }
+
+    /// ACPI EC RD_EC (0x80)
+    pub fn acpi_read<S: Read + Write>(&self, client: &mut Client<S>, addr: u8) -> Result<u8, ClientError> {
+        self.command(client, 0x80)?;
+        self.write(client, addr)?;
+        self.read(client)
+    }
+
+    /// ACPI EC WR_EC (0x81)
+    pub fn acpi_write<S: Read + Write>(&self, client: &mut Client<S>, addr: u8, value: u8) -> Result<(), ClientError> {
+        self.command(client, 0x81)?;
+        self.write(client, addr)?;
+        self.write(client, value)
+    }
+
+    /// ACPI EC BE_EC (0x82), returning the burst acknowledge byte, normally 0x90
+    pub fn acpi_burst_enable<S: Read + Write>(&self, client: &mut Client<S>) -> Result<u8, ClientError> {
+        self.command(client, 0x82)?;
+        self.read(client)
+    }
+
+    /// ACPI EC BD_EC (0x83)
+    pub fn acpi_burst_disable<S: Read + Write>(&self, client: &mut Client<S>) -> Result<(), ClientError> {
+        self.command(client, 0x83)?;
+        client.wait(self.command, pmc::STATUS_IBF, 0)?;
+        Ok(())
+    }
+
+    /// ACPI EC QR_EC (0x84), returning the event number, or 0 if there is none
+    pub fn acpi_query<S: Read + Write>(&self, client: &mut Client<S>) -> Result<u8, ClientError> {
+        self.command(client, 0x84)?;
+        self.read(client)
+    }
+}
+
+/// Keyboard controller, as seen from the host
+#[derive(Clone, Copy, Debug)]
+pub struct Kbc {
+    pub data: u16,
+    pub command: u16,
+}
+
+impl Kbc {
+    pub const DEFAULT: Kbc = Kbc::new(0x60, 0x64);
+
+    pub const fn new(data: u16, command: u16) -> Self {
+        Self { data, command }
+    }
+
+    pub fn status<S: Read + Write>(&self, client: &mut Client<S>) -> Result<u8, ClientError> {
+        client.inb(self.command)
+    }
+
+    /// Send a command once the input buffer is empty
+    pub fn command<S: Read + Write>(&self, client: &mut Client<S>, value: u8) -> Result<(), ClientError> {
+        client.wait(self.command, kbc::STATUS_IBF, 0)?;
+        client.outb(self.command, value)
+    }
+
+    /// Send data once the input buffer is empty
+    pub fn write<S: Read + Write>(&self, client: &mut Client<S>, value: u8) -> Result<(), ClientError> {
+        client.wait(self.command, kbc::STATUS_IBF, 0)?;
+        client.outb(self.data, value)
+    }
+
+    /// Read data once the output buffer is full, returning it and if it came from the mouse
+    pub fn read<S: Read + Write>(&self, client: &mut Client<S>) -> Result<(u8, bool), ClientError> {
+        let status = client.wait(self.command, kbc::STATUS_OBF, kbc::STATUS_OBF)?;
+        let value = client.inb(self.data)?;
+        Ok((value, status & kbc::STATUS_AUX != 0))
+    }
+}
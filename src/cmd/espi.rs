@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::Ec;
+use ecsim::espi::{CONFIG_OFFSETS, espi_reset, get_configuration, set_configuration};
+
+pub fn espi(ec: &mut Ec, args: &[&str]) {
+    match args {
+        [] => {
+            eprintln!("espi:");
+            for offset in CONFIG_OFFSETS.iter() {
+                match get_configuration(ec, *offset) {
+                    Ok(value) => eprintln!("  {:02X}: {:08X}", offset, value),
+                    Err(_) => {
+                        eprintln!("eSPI not supported on {:04X}", ec.id);
+                        return;
+                    }
+                }
+            }
+            eprintln!("  ESGCTRL0: {:02X}", ec.mcu.lock().unwrap().xram[0x31A0]);
+        },
+        ["reset"] => {
+            if let Err(err) = espi_reset(ec) {
+                eprintln!("failed to reset eSPI: {:?}", err);
+            }
+        },
+        ["set", offset, value] => {
+            let offset = match u16::from_str_radix(offset, 16) {
+                Ok(ok) => ok,
+                Err(err) => {
+                    eprintln!("invalid offset '{}': {}", offset, err);
+                    return;
+                }
+            };
+            let value = match u32::from_str_radix(value, 16) {
+                Ok(ok) => ok,
+                Err(err) => {
+                    eprintln!("invalid value '{}': {}", value, err);
+                    return;
+                }
+            };
+            if let Err(err) = set_configuration(ec, offset, value) {
+                eprintln!("failed to set eSPI configuration {:02X}: {:?}", offset, err);
+            }
+        },
+        _ => {
+            eprintln!("espi [reset | set offset value]");
+        }
+    }
+}
@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: MIT
+
+use std::fs;
+
+use ecsim::Ec;
+use ecsim::flash::{self, CHIPS};
+
+pub fn flash(ec: &mut Ec, args: &[&str]) {
+    match args {
+        [] => {
+            if let Some(overlay) = &ec.flash.overlay {
+                eprintln!("flash: overlay {}", overlay.display());
+            } else {
+                for (name, path) in CHIPS.iter().zip(ec.flash.paths.iter()) {
+                    if let Some((path, _)) = path {
+                        eprintln!("flash {}: {}", name, path.display());
+                    }
+                }
+            }
+            let pages = flash::changed_pages(ec);
+            for (chip, name) in CHIPS.iter().enumerate() {
+                let count = pages.iter().filter(|page| page.0 == chip).count();
+                eprintln!("flash {}: {} pages changed", name, count);
+            }
+        },
+        ["save"] => {
+            if let Err(err) = flash::save(ec) {
+                eprintln!("failed to save flash: {}", err);
+            }
+        },
+        ["revert"] => {
+            flash::revert(ec);
+        },
+        ["changes"] => {
+            let changes = ec.flash.changes.lock().unwrap();
+            eprintln!("flash changes:");
+            for ((chip, offset), history) in changes.iter() {
+                eprintln!("{} {:06X}:", CHIPS[*chip], offset);
+                for change in history.iter() {
+                    eprintln!(
+                        "    step {} pc {:04X}: {} by {}, {} writes",
+                        change.step,
+                        change.pc,
+                        change.command,
+                        change.path,
+                        change.writes
+                    );
+                }
+            }
+        },
+        ["diff"] => {
+            eprint!("{}", flash::diff(ec));
+        },
+        ["diff", path] => {
+            if let Err(err) = fs::write(path, flash::diff(ec)) {
+                eprintln!("failed to write {}: {}", path, err);
+            }
+        },
+        _ => {
+            eprintln!("flash [save | revert | changes | diff [path]]");
+        }
+    }
+}
@@ -1,8 +1,6 @@
 // SPDX-License-Identifier: MIT
 
-use area8051::Isa;
-
-use crate::Ec;
+use ecsim::Ec;
 
 pub fn int(ec: &mut Ec, args: &[&str]) {
     if args.len() != 1 {
@@ -25,9 +23,5 @@ pub fn int(ec: &mut Ec, args: &[&str]) {
         }
     };
 
-    let mut mcu = ec.mcu.lock().unwrap();
-    let pc = mcu.pc();
-    mcu.push_sp(pc as u8);
-    mcu.push_sp((pc >> 8) as u8);
-    mcu.set_pc(0x0003 + (int as u16) * 8);
+    ec.interrupt(int);
 }
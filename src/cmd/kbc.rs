@@ -1,15 +1,7 @@
 // SPDX-License-Identifier: MIT
 
-use crate::Ec;
-
-const STATUS_CMD: u8 = 1 << 3;
-const STATUS_IBF: u8 = 1 << 1;
-const STATUS_OBF: u8 = 1 << 0;
-
-const STATUS: usize = 0x1304;
-const DATA_KEYBOARD: usize = 0x1506;
-const DATA_MOUSE: usize = 0x1508;
-const DATA_IN: usize = 0x130A;
+use ecsim::Ec;
+use ecsim::kbc::{DATA_IN, DATA_KEYBOARD, DATA_MOUSE, STATUS, STATUS_AUX, STATUS_CMD, STATUS_IBF, STATUS_OBF};
 
 pub fn cmd(ec: &mut Ec, args: &[&str]) {
     if args.len() != 1 {
@@ -33,8 +25,7 @@ pub fn cmd(ec: &mut Ec, args: &[&str]) {
 
 pub fn keyboard(ec: &mut Ec, _args: &[&str]) {
     let mut mcu = ec.mcu.lock().unwrap();
-    //TODO: Determine if byte came from keyboard
-    if mcu.xram[STATUS] & STATUS_OBF != 0 {
+    if mcu.xram[STATUS] & (STATUS_OBF | STATUS_AUX) == STATUS_OBF {
         eprintln!("{:02X}", mcu.xram[DATA_KEYBOARD]);
         mcu.xram[STATUS] &= !STATUS_OBF;
     }
@@ -42,8 +33,7 @@ pub fn keyboard(ec: &mut Ec, _args: &[&str]) {
 
 pub fn mouse(ec: &mut Ec, _args: &[&str]) {
     let mut mcu = ec.mcu.lock().unwrap();
-    //TODO: Determine if byte came from mouse
-    if mcu.xram[STATUS] & STATUS_OBF != 0 {
+    if mcu.xram[STATUS] & (STATUS_OBF | STATUS_AUX) == (STATUS_OBF | STATUS_AUX) {
         eprintln!("{:02X}", mcu.xram[DATA_MOUSE]);
         mcu.xram[STATUS] &= !STATUS_OBF;
     }
@@ -2,6 +2,13 @@
 
 #![allow(clippy::from_str_radix_10)]
 
+pub mod espi;
+pub mod flash;
 pub mod int;
 pub mod kbc;
 pub mod pmc;
+pub mod post;
+pub mod sci;
+pub mod spi;
+pub mod uart;
+pub mod vw;
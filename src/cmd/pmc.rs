@@ -1,18 +1,25 @@
 // SPDX-License-Identifier: MIT
 
-use crate::Ec;
+use ecsim::Ec;
+use ecsim::pmc::{PMC_REGS, PmcRegs, STATUS_CMD, STATUS_IBF, STATUS_OBF};
 
-const STATUS_CMD: u8 = 1 << 3;
-const STATUS_IBF: u8 = 1 << 1;
-const STATUS_OBF: u8 = 1 << 0;
-
-const STATUS: usize = 0x1500;
-const DATA_OUT: usize = 0x1501;
-const DATA_IN: usize = 0x1504;
+/// Get the registers of the channel given as an optional argument, defaulting to PMC1
+fn channel(arg_opt: Option<&&str>) -> Option<&'static PmcRegs> {
+    match arg_opt {
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(channel @ 1 ..= 5) => Some(&PMC_REGS[channel - 1]),
+            _ => {
+                eprintln!("channel '{}' is not from 1 to 5", arg);
+                None
+            }
+        },
+        None => Some(&PMC_REGS[0]),
+    }
+}
 
 pub fn cmd(ec: &mut Ec, args: &[&str]) {
-    if args.len() != 1 {
-        eprintln!("pmc_cmd [argument in hex]");
+    if args.is_empty() || args.len() > 2 {
+        eprintln!("pmc_cmd [argument in hex] [channel]");
         return;
     }
 
@@ -20,27 +27,37 @@ pub fn cmd(ec: &mut Ec, args: &[&str]) {
         Ok(ok) => ok,
         Err(err) => {
             eprintln!("argument '{}' failed to parse as hex: {}", args[0], err);
-            eprintln!("pmc_cmd [argument in hex]");
+            eprintln!("pmc_cmd [argument in hex] [channel]");
             return;
         }
     };
 
+    let regs = match channel(args.get(1)) {
+        Some(some) => some,
+        None => return,
+    };
+
     let mut mcu = ec.mcu.lock().unwrap();
-    mcu.xram[STATUS] |= STATUS_CMD | STATUS_IBF;
-    mcu.xram[DATA_IN] = data;
+    mcu.xram[regs.sts] |= STATUS_CMD | STATUS_IBF;
+    mcu.xram[regs.data_in] = data;
 }
 
-pub fn read(ec: &mut Ec, _args: &[&str]) {
+pub fn read(ec: &mut Ec, args: &[&str]) {
+    let regs = match channel(args.get(0)) {
+        Some(some) => some,
+        None => return,
+    };
+
     let mut mcu = ec.mcu.lock().unwrap();
-    if mcu.xram[STATUS] & STATUS_OBF != 0 {
-        eprintln!("{:02X}", mcu.xram[DATA_OUT]);
-        mcu.xram[STATUS] &= !STATUS_OBF;
+    if mcu.xram[regs.sts] & STATUS_OBF != 0 {
+        eprintln!("{:02X}", mcu.xram[regs.data_out]);
+        mcu.xram[regs.sts] &= !STATUS_OBF;
     }
 }
 
 pub fn write(ec: &mut Ec, args: &[&str]) {
-    if args.len() != 1 {
-        eprintln!("pmc_write [hex argument]");
+    if args.is_empty() || args.len() > 2 {
+        eprintln!("pmc_write [hex argument] [channel]");
         return;
     }
 
@@ -48,13 +65,18 @@ pub fn write(ec: &mut Ec, args: &[&str]) {
         Ok(ok) => ok,
         Err(err) => {
             eprintln!("argument '{}' failed to parse as hex: {}", args[0], err);
-            eprintln!("pmc_write [hex argument]");
+            eprintln!("pmc_write [hex argument] [channel]");
             return;
         }
     };
 
+    let regs = match channel(args.get(1)) {
+        Some(some) => some,
+        None => return,
+    };
+
     let mut mcu = ec.mcu.lock().unwrap();
-    mcu.xram[STATUS] &= !STATUS_CMD;
-    mcu.xram[STATUS] |= STATUS_IBF;
-    mcu.xram[DATA_IN] = data;
+    mcu.xram[regs.sts] &= !STATUS_CMD;
+    mcu.xram[regs.sts] |= STATUS_IBF;
+    mcu.xram[regs.data_in] = data;
 }
@@ -0,0 +1,20 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::Ec;
+
+pub fn post(ec: &mut Ec, args: &[&str]) {
+    match args {
+        [] => {
+            eprintln!("post:");
+            for (steps, port, value) in ec.post_codes.iter() {
+                eprintln!("{:>12}: {:02X} = {:02X}", steps, port, value);
+            }
+        },
+        ["clear"] => {
+            ec.post_codes.clear();
+        },
+        _ => {
+            eprintln!("post [clear]");
+        }
+    }
+}
@@ -0,0 +1,21 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::Ec;
+
+pub fn sci(ec: &mut Ec, args: &[&str]) {
+    let mut scis = ec.scis.lock().unwrap();
+    match args {
+        [] => {
+            eprintln!("sci:");
+            for (steps, source) in scis.iter() {
+                eprintln!("{:>12}: {}", steps, source);
+            }
+        },
+        ["clear"] => {
+            scis.clear();
+        },
+        _ => {
+            eprintln!("sci [clear]");
+        }
+    }
+}
@@ -0,0 +1,33 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::Ec;
+
+pub fn spi(ec: &mut Ec, args: &[&str]) {
+    let chips = [("internal", &ec.spi), ("external", &ec.spi_external)];
+    match args {
+        [] => {
+            for (name, spi) in chips.iter() {
+                let spi = spi.lock().unwrap();
+                eprintln!(
+                    "spi {} ({}): status {:02X} {:02X} {:02X}",
+                    name,
+                    spi.device.name,
+                    spi.status[0],
+                    spi.status[1],
+                    spi.status[2]
+                );
+                for error in spi.errors.iter() {
+                    eprintln!("  {}", error);
+                }
+            }
+        },
+        ["clear"] => {
+            for (_, spi) in chips.iter() {
+                spi.lock().unwrap().errors.clear();
+            }
+        },
+        _ => {
+            eprintln!("spi [clear]");
+        }
+    }
+}
@@ -0,0 +1,14 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::Ec;
+
+pub fn write(ec: &mut Ec, args: &[&str]) {
+    let mut uart = ec.uart.lock().unwrap();
+    for (i, arg) in args.iter().enumerate() {
+        if i != 0 {
+            uart.rx_queue.push_back(b' ');
+        }
+        uart.rx_queue.extend(arg.bytes());
+    }
+    uart.rx_queue.push_back(b'\n');
+}
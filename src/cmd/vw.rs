@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::Ec;
+use ecsim::espi::{WIRES, vw_get, vw_host_driven, vw_set};
+
+pub fn vw(ec: &mut Ec, args: &[&str]) {
+    match args {
+        [] => {
+            eprintln!("vw:");
+            for wire in WIRES.iter() {
+                let value = match vw_get(ec, wire.index) {
+                    Ok(ok) => ok,
+                    Err(_) => {
+                        eprintln!("virtual wires not supported on {:04X}", ec.id);
+                        return;
+                    }
+                };
+                let state = if value & (0x10 << wire.shift) == 0 {
+                    "invalid"
+                } else if value & (1 << wire.shift) == 0 {
+                    "0"
+                } else {
+                    "1"
+                };
+                let driver = if vw_host_driven(wire.index) { "host" } else { "ec" };
+                eprintln!("  {:<16} {:02X}:{} {:<4} {}", wire.name, wire.index, wire.shift, driver, state);
+            }
+        },
+        [name, level] => {
+            let wire = match WIRES.iter().find(|wire| wire.name == *name) {
+                Some(some) => some,
+                None => {
+                    eprintln!("unknown virtual wire '{}'", name);
+                    return;
+                }
+            };
+            let level = match *level {
+                "0" => 0,
+                "1" => 1,
+                _ => {
+                    eprintln!("level '{}' is not 0 or 1", level);
+                    return;
+                }
+            };
+            if let Err(err) = vw_set(ec, wire.index, (0x10 | level) << wire.shift) {
+                eprintln!("failed to send virtual wire '{}': {:?}", name, err);
+            }
+        },
+        _ => {
+            eprintln!("vw [name 0|1]");
+        }
+    }
+}
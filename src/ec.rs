@@ -1,37 +1,133 @@
 // SPDX-License-Identifier: MIT
 
 use area8051::{Addr, Isa, Mcu, Mem, Reg};
+use std::collections::VecDeque;
 use std::sync::Mutex;
 
-use crate::{Spi, xram};
+use crate::{Spi, Uart, espi, spi, xram};
+use crate::event::{Event, Scheduler};
+use crate::flash::FlashFiles;
+use crate::host::Notification;
+use crate::superio::SuperIo;
+use crate::uart::StdioUart;
+
+/// SCIs kept for the sci command
+const SCIS_MAX: usize = 1024;
 
 pub struct Ec {
     pub id: u16,
     pub version: u8,
     pub mcu: Mutex<Mcu>,
+    /// SPI interface of the internal flash, in program memory
     pub spi: Mutex<Spi>,
+    /// SPI interface of the external flash, in xmem
+    pub spi_external: Mutex<Spi>,
     pub xmem: Mutex<Box<[u8]>>,
-    pub superio_addr: u8,
+    /// Images and files the flash contents are saved to
+    pub flash: FlashFiles,
+    pub uart: Mutex<Uart>,
+    pub events: Mutex<Scheduler>,
+    /// Notifications waiting to be sent to host clients
+    pub notifications: Mutex<Vec<Notification>>,
+    /// SCIs raised by firmware, as step and source
+    pub scis: Mutex<VecDeque<(u64, &'static str)>>,
+    /// SuperIO configuration, shared by host cycles and EC2I
+    pub superio: Mutex<SuperIo>,
+    /// POST codes written by the host to port 0x80 or 0x81, as step, port, and value
+    pub post_codes: VecDeque<(u64, u16, u8)>,
     pub steps: u64,
+    /// Interrupt currently being serviced, cleared by RETI
+    pub isr: Option<u8>,
 }
 
 impl Ec {
     pub fn new(id: u16, version: u8, pmem: Box<[u8]>, xmem: Box<[u8]>) -> Self {
+        let flash = FlashFiles::new(&pmem, &xmem);
         Self {
             id,
             version,
             mcu: Mutex::new(Mcu::new(pmem)),
-            spi: Mutex::new(Spi::new()),
+            spi: Mutex::new(Spi::new(&spi::ITE)),
+            spi_external: Mutex::new(Spi::new(&spi::W25Q16JV)),
             xmem: Mutex::new(xmem),
-            superio_addr: 0,
+            flash,
+            uart: Mutex::new(Uart::new(Box::new(StdioUart))),
+            events: Mutex::new(Scheduler::new()),
+            notifications: Mutex::new(Vec::new()),
+            scis: Mutex::new(VecDeque::new()),
+            superio: Mutex::new(SuperIo::new()),
+            post_codes: VecDeque::new(),
             steps: 0,
+            isr: None,
+        }
+    }
+
+    /// Create an Ec running a ROM, filled to 128 KiB, with a copy in the external flash
+    pub fn from_rom(id: u16, version: u8, mut rom: Vec<u8>) -> Self {
+        // Fill program memory to 128 KiB
+        while rom.len() < 128 * 1024 {
+            rom.push(0xFF);
+        }
+
+        let xmem = rom.clone();
+
+        Self::from_images(id, version, rom, xmem)
+    }
+
+    /// Create an Ec running from internal and external flash images, reset and ready to run
+    pub fn from_images(id: u16, version: u8, pmem: Vec<u8>, xmem: Vec<u8>) -> Self {
+        let mut ec = Self::new(id, version, pmem.into_boxed_slice(), xmem.into_boxed_slice());
+        ec.reset();
+        ec
+    }
+
+    /// Call an interrupt vector (0x0003 + int * 8) like the interrupt hardware does
+    pub fn interrupt(&mut self, int: u8) {
+        let mut mcu = self.mcu.lock().unwrap();
+        let pc = mcu.pc();
+        mcu.push_sp(pc as u8);
+        mcu.push_sp((pc >> 8) as u8);
+        mcu.set_pc(0x0003 + (int as u16) * 8);
+        self.isr = Some(int);
+    }
+
+    /// Record an SCI and notify host clients
+    pub fn sci(&self, source: &'static str) {
+        let mut scis = self.scis.lock().unwrap();
+        if scis.len() >= SCIS_MAX {
+            scis.pop_front();
         }
+        scis.push_back((self.steps, source));
+        self.notifications.lock().unwrap().push(Notification::Sci);
     }
 
+    /// Make sure timers and the serial port get machine cycles after being started
+    pub fn wake_machine_cycle(&self) {
+        let time = (self.steps / 12 + 1) * 12;
+        self.events.lock().unwrap().schedule_once(time, Event::MachineCycle);
+    }
+
+    /// Track the end of the interrupt service routine, call before each step
+    pub fn check_reti(&mut self) {
+        if self.isr.is_some() && self.load(Addr::PMem(self.pc())) == 0x32 {
+            self.isr = None;
+        }
+    }
+
+    /// Index of an xram address in storage. The IT5570 maps SRAM 0x8000 - 0x8FFF onto 0x0000 -
+    /// 0x0FFF, which is where the data is kept
+    pub fn sram(&self, address: usize) -> usize {
+        match address {
+            0x8000 ..= 0x8FFF if self.id == 0x5570 => address - 0x8000,
+            _ => address,
+        }
+    }
+
+    /// SCAR registers, with the SRAM base and size they map code to
     pub fn scar(&self) -> &'static [(usize, usize, usize)] {
         match self.id {
             0x5570 => &[
-                (0x1040, 0x0000, 4096),
+                (0x1040, 0x8000, 4096),
             ],
             0x8587 => &[
                 (0x1040, 0x0000, 2048),
@@ -79,12 +175,16 @@ impl Mem for Ec {
                     };
 
                     if real >= value && real < value + size {
-                        return mcu.xram[(real - value) + base];
+                        return mcu.xram[self.sram((real - value) + base)];
                     }
                 }
 
                 mcu.pmem[real]
             },
+            // SBUF reads return the receive buffer
+            Addr::Reg(0x99) => {
+                self.uart.lock().unwrap().rx_buffer
+            },
             _ => {
                 let mcu = self.mcu.lock().unwrap();
                 mcu.load(addr)
@@ -97,6 +197,32 @@ impl Mem for Ec {
             Addr::XRam(i) => {
                 xram(self, i, Some(value));
             },
+            // SBUF writes start transmission
+            Addr::Reg(0x99) => {
+                let scon = {
+                    let mut mcu = self.mcu.lock().unwrap();
+                    mcu.store(addr, value);
+                    mcu.load(Addr::Reg(0x98))
+                };
+                self.uart.lock().unwrap().transmit(scon, value);
+                self.wake_machine_cycle();
+            },
+            // TCON, SCON, and T2CON writes may start timers or reception
+            Addr::Reg(0x88) | Addr::Reg(0x98) | Addr::Reg(0xC8) => {
+                self.mcu.lock().unwrap().store(addr, value);
+
+                let start_mask = match addr {
+                    // TR0 and TR1
+                    Addr::Reg(0x88) => 0b0101_0000,
+                    // REN
+                    Addr::Reg(0x98) => 0b0001_0000,
+                    // TR2
+                    _ => 0b0000_0100,
+                };
+                if value & start_mask != 0 {
+                    self.wake_machine_cycle();
+                }
+            },
             _ => {
                 let mut mcu = self.mcu.lock().unwrap();
                 mcu.store(addr, value);
@@ -127,6 +253,8 @@ impl Isa for Ec {
         } else {
             0b1011_1111
         };
+        // FMSSR decodes 128 KiB
+        mcu.xram[0x1007] = 0b0000_0001;
         mcu.xram[0x1020] = 0b0000_1000;
         mcu.xram[0x1032] = 0b0000_0011;
         mcu.xram[0x1036] = 0b1000_0000;
@@ -213,35 +341,7 @@ impl Isa for Ec {
         };
 
         if self.id == 0x5570 {
-            // eSPI slave
-            mcu.xram[0x3104] = 0b0000_0011;
-            mcu.xram[0x3105] = 0b0000_0010;
-            mcu.xram[0x3107] = 0b0000_1111;
-            mcu.xram[0x310A] = 0b0001_0001;
-            mcu.xram[0x310E] = 0b0000_0111;
-            mcu.xram[0x3112] = 0b0000_0001;
-            mcu.xram[0x3113] = 0b0001_0000;
-            mcu.xram[0x3116] = 0b0001_0001;
-            mcu.xram[0x3117] = 0b0010_0100;
-            mcu.xram[0x311A] = 0b0000_0100;
-            mcu.xram[0x311B] = 0b0000_0001;
-
-            // eSPI VW
-            mcu.xram[0x3200] = 0b0000_0011;
-            mcu.xram[0x3202] = 0b0000_0011;
-            mcu.xram[0x3203] = 0b0000_0011;
-            mcu.xram[0x3204] = 0b0000_0011;
-            mcu.xram[0x3205] = 0b0000_0011;
-            mcu.xram[0x3206] = 0b0000_0011;
-            mcu.xram[0x3207] = 0b0000_0011;
-            mcu.xram[0x3240] = 0b0000_0011;
-            mcu.xram[0x3241] = 0b0000_0011;
-            mcu.xram[0x3242] = 0b0000_0011;
-            mcu.xram[0x3243] = 0b0000_0011;
-            mcu.xram[0x3244] = 0b0000_0011;
-            mcu.xram[0x3245] = 0b0000_0011;
-            mcu.xram[0x3246] = 0b0000_0011;
-            mcu.xram[0x3247] = 0b0000_0011;
+            espi::reset(&mut mcu.xram);
         }
     }
 }
@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: MIT
+
+use crate::Ec;
+use crate::host::HostError;
+
+/// Offsets of the slave configuration registers
+pub const CONFIG_OFFSETS: [u16; 5] = [0x08, 0x10, 0x20, 0x30, 0x40];
+
+/// Slave configuration registers, as eSPI offset, XRAM address of the most significant byte, and
+/// the bits the host can set with SET_CONFIGURATION
+const CONFIGS: [(u16, usize, u32); 5] = [
+    // General capabilities and configurations
+    (0x08, 0x3104, 0xDCF0_F000),
+    // Peripheral channel
+    (0x10, 0x3108, 0x0000_7705),
+    // Virtual wire channel
+    (0x20, 0x310C, 0x003F_0001),
+    // OOB message channel
+    (0x30, 0x3110, 0x0000_0701),
+    // Flash access channel
+    (0x40, 0x3114, 0x0000_770D),
+];
+
+/// Channel enable and ready bits of channel configuration registers
+const CHANNEL_ENABLE: u32 = 1 << 0;
+const CHANNEL_READY: u32 = 1 << 1;
+
+/// ESGCTRL0, with a status bit per channel set when the host changes its enable bit
+const ESGCTRL0: usize = 0x31A0;
+
+/// Reset values of the eSPI slave and virtual wire registers, also applied on eSPI reset
+pub fn reset(xram: &mut [u8]) {
+    // eSPI slave
+    for &(_, reg, _) in CONFIGS.iter() {
+        for i in 0..4 {
+            xram[reg + i] = 0;
+        }
+    }
+    xram[0x3104] = 0b0000_0011;
+    xram[0x3105] = 0b0000_0010;
+    xram[0x3107] = 0b0000_1111;
+    xram[0x310A] = 0b0001_0001;
+    xram[0x310E] = 0b0000_0111;
+    xram[0x3112] = 0b0000_0001;
+    xram[0x3113] = 0b0001_0000;
+    xram[0x3116] = 0b0001_0001;
+    xram[0x3117] = 0b0010_0100;
+    xram[0x311A] = 0b0000_0100;
+    xram[0x311B] = 0b0000_0001;
+
+    // eSPI VW
+    xram[0x3200] = 0b0000_0011;
+    for index in 0x02..=0x07 {
+        xram[0x3200 + index] = 0b0000_0011;
+    }
+    for index in 0x40..=0x47 {
+        xram[0x3200 + index] = 0b0000_0011;
+    }
+}
+
+fn config_load(xram: &[u8], reg: usize) -> u32 {
+    u32::from_be_bytes([xram[reg], xram[reg + 1], xram[reg + 2], xram[reg + 3]])
+}
+
+fn config_store(xram: &mut [u8], reg: usize, value: u32) {
+    xram[reg .. reg + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Handle GET_CONFIGURATION from the host
+pub fn get_configuration(ec: &Ec, offset: u16) -> Result<u32, HostError> {
+    if ec.id != 0x5570 {
+        return Err(HostError::Unimplemented);
+    }
+    let &(_, reg, _) = CONFIGS.iter().find(|config| config.0 == offset).ok_or(HostError::Unimplemented)?;
+    Ok(config_load(&ec.mcu.lock().unwrap().xram, reg))
+}
+
+/// Handle SET_CONFIGURATION from the host. Bits the host cannot set are unchanged, and changing a
+/// channel enable bit sets its status in ESGCTRL0. The peripheral and virtual wire channels become
+/// ready when enabled, while firmware sets the ready bits of the others
+pub fn set_configuration(ec: &mut Ec, offset: u16, value: u32) -> Result<(), HostError> {
+    if ec.id != 0x5570 {
+        return Err(HostError::Unimplemented);
+    }
+    let (channel, &(_, reg, mask)) = CONFIGS.iter().enumerate()
+        .find(|(_, config)| config.0 == offset)
+        .ok_or(HostError::Unimplemented)?;
+
+    let mut mcu = ec.mcu.lock().unwrap();
+    let old = config_load(&mcu.xram, reg);
+    let mut new = (old & !mask) | (value & mask);
+    if channel > 0 {
+        let channel = channel - 1;
+        if (old ^ new) & CHANNEL_ENABLE != 0 {
+            mcu.xram[ESGCTRL0] |= 1 << channel;
+        }
+        if new & CHANNEL_ENABLE == 0 {
+            new &= !CHANNEL_READY;
+        } else if channel <= 1 {
+            new |= CHANNEL_READY;
+        }
+    }
+    config_store(&mut mcu.xram, reg, new);
+    Ok(())
+}
+
+/// Assert eSPI reset from the host, returning the slave to its reset configuration
+pub fn espi_reset(ec: &mut Ec) -> Result<(), HostError> {
+    if ec.id != 0x5570 {
+        return Err(HostError::Unimplemented);
+    }
+    let mut mcu = ec.mcu.lock().unwrap();
+    // Channels enabled before the reset are reported as changed
+    for (channel, &(_, reg, _)) in CONFIGS.iter().enumerate().skip(1) {
+        if config_load(&mcu.xram, reg) & CHANNEL_ENABLE != 0 {
+            mcu.xram[ESGCTRL0] |= 1 << (channel - 1);
+        }
+    }
+    reset(&mut mcu.xram);
+    Ok(())
+}
+
+/// Virtual wire, as a bit of a virtual wire index register. Each index register holds four wire
+/// levels in bits 3:0, and their valid bits in bits 7:4
+pub struct VirtualWire {
+    pub name: &'static str,
+    pub index: u8,
+    pub shift: u8,
+}
+
+pub const WIRES: [VirtualWire; 26] = [
+    VirtualWire { name: "slp_s3", index: 0x02, shift: 0 },
+    VirtualWire { name: "slp_s4", index: 0x02, shift: 1 },
+    VirtualWire { name: "slp_s5", index: 0x02, shift: 2 },
+    VirtualWire { name: "sus_stat", index: 0x03, shift: 0 },
+    VirtualWire { name: "pltrst", index: 0x03, shift: 1 },
+    VirtualWire { name: "oob_rst_warn", index: 0x03, shift: 2 },
+    VirtualWire { name: "oob_rst_ack", index: 0x04, shift: 0 },
+    VirtualWire { name: "wake", index: 0x04, shift: 2 },
+    VirtualWire { name: "pme", index: 0x04, shift: 3 },
+    VirtualWire { name: "boot_load_done", index: 0x05, shift: 0 },
+    VirtualWire { name: "error_fatal", index: 0x05, shift: 1 },
+    VirtualWire { name: "error_nonfatal", index: 0x05, shift: 2 },
+    VirtualWire { name: "boot_load_status", index: 0x05, shift: 3 },
+    VirtualWire { name: "sci", index: 0x06, shift: 0 },
+    VirtualWire { name: "smi", index: 0x06, shift: 1 },
+    VirtualWire { name: "rcin", index: 0x06, shift: 2 },
+    VirtualWire { name: "host_rst_ack", index: 0x06, shift: 3 },
+    VirtualWire { name: "host_rst_warn", index: 0x07, shift: 0 },
+    VirtualWire { name: "smiout", index: 0x07, shift: 1 },
+    VirtualWire { name: "nmiout", index: 0x07, shift: 2 },
+    VirtualWire { name: "sus_ack", index: 0x40, shift: 0 },
+    VirtualWire { name: "sus_warn", index: 0x41, shift: 0 },
+    VirtualWire { name: "sus_pwrdn_ack", index: 0x41, shift: 1 },
+    VirtualWire { name: "slp_a", index: 0x41, shift: 3 },
+    VirtualWire { name: "slp_lan", index: 0x42, shift: 0 },
+    VirtualWire { name: "slp_wlan", index: 0x42, shift: 1 },
+];
+
+/// Get the XRAM address of a virtual wire index register
+pub fn vw_register(index: u8) -> Option<usize> {
+    match index {
+        0x02 ..= 0x07 | 0x40 ..= 0x47 => Some(0x3200 + index as usize),
+        _ => None,
+    }
+}
+
+/// Check if a virtual wire index is driven by the host, instead of the EC
+pub fn vw_host_driven(index: u8) -> bool {
+    matches!(index, 0x02 | 0x03 | 0x07 | 0x41 ..= 0x44)
+}
+
+/// Read a virtual wire index register
+pub fn vw_get(ec: &Ec, index: u8) -> Result<u8, HostError> {
+    if ec.id != 0x5570 {
+        return Err(HostError::Unimplemented);
+    }
+    let reg = vw_register(index).ok_or(HostError::Unimplemented)?;
+    Ok(ec.mcu.lock().unwrap().xram[reg])
+}
+
+/// Send a virtual wire index from the host. Wires with their valid bit set in bits 7:4 take the
+/// level in bits 3:0, and the rest are unchanged
+pub fn vw_set(ec: &mut Ec, index: u8, value: u8) -> Result<(), HostError> {
+    if ec.id != 0x5570 {
+        return Err(HostError::Unimplemented);
+    }
+    let reg = vw_register(index).ok_or(HostError::Unimplemented)?;
+    if ! vw_host_driven(index) {
+        return Err(HostError::Protected);
+    }
+
+    let mut mcu = ec.mcu.lock().unwrap();
+    let valid = value >> 4;
+    let old = mcu.xram[reg];
+    mcu.xram[reg] = (old & !valid) | (value & valid) | (valid << 4);
+    Ok(())
+}
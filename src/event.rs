@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: MIT
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// Peripheral activity scheduled at a point in virtual time
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Poll the host socket for requests
+    HostPoll,
+    /// Advance timers and the serial port by one machine cycle (12 steps)
+    MachineCycle,
+}
+
+struct Entry {
+    time: u64,
+    // Keeps events scheduled for the same time in order
+    seq: u64,
+    event: Event,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.seq).cmp(&(other.time, other.seq))
+    }
+}
+
+/// Event queue keyed on virtual time, measured in steps
+pub struct Scheduler {
+    queue: BinaryHeap<Reverse<Entry>>,
+    seq: u64,
+}
+
+#[allow(clippy::new_without_default)]
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            queue: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    /// Schedule event to happen at time
+    pub fn schedule(&mut self, time: u64, event: Event) {
+        self.queue.push(Reverse(Entry {
+            time,
+            seq: self.seq,
+            event,
+        }));
+        self.seq += 1;
+    }
+
+    /// Schedule event to happen at time, unless it is already scheduled
+    pub fn schedule_once(&mut self, time: u64, event: Event) {
+        if ! self.scheduled(event) {
+            self.schedule(time, event);
+        }
+    }
+
+    /// Check if event is scheduled
+    pub fn scheduled(&self, event: Event) -> bool {
+        self.queue.iter().any(|entry| entry.0.event == event)
+    }
+
+    /// Time of the next event
+    pub fn next_time(&self) -> Option<u64> {
+        self.queue.peek().map(|entry| entry.0.time)
+    }
+
+    /// Remove the next event if it is due at time now
+    pub fn pop(&mut self, now: u64) -> Option<Event> {
+        if self.next_time()? <= now {
+            self.queue.pop().map(|entry| entry.0.event)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: MIT
+
+//! Flash contents kept across runs, either written back to the image files or saved as an
+//! overlay of changed pages, leaving the images unchanged.
+//!
+//! An overlay starts with `OVERLAY_MAGIC`, followed by records of a chip byte (0 for internal,
+//! 1 for external), a 32-bit little endian offset, and `PAGE_SIZE` bytes of data.
+
+use std::{fs, io};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::{Ec, Spi};
+
+pub const OVERLAY_MAGIC: &[u8; 8] = b"ECSIMOV1";
+pub const PAGE_SIZE: usize = 256;
+
+/// Names of the flash chips, as indexes into `FlashFiles`
+pub const CHIPS: [&str; 2] = ["internal", "external"];
+
+/// Changes kept for each page, older ones are dropped
+const CHANGES_MAX: usize = 64;
+
+/// Rows of unchanged context around changes in `diff`
+const DIFF_CONTEXT: usize = 3;
+
+/// Change of a flash page during this run
+pub struct PageChange {
+    /// How the page was reached, like "ecindar" or "host follow"
+    pub path: &'static str,
+    /// SPI command, or "write" for direct writes
+    pub command: &'static str,
+    pub step: u64,
+    pub pc: u16,
+    /// Number of writes to the page by this command, at this step
+    pub writes: u64,
+}
+
+pub struct FlashFiles {
+    /// Contents of the internal and external flash when loaded, before any overlay
+    pub base: [Box<[u8]>; 2],
+    /// Files the internal and external flash are written back to when saved without an overlay,
+    /// with their lengths when loaded, so that saving does not write back the padding
+    pub paths: [Option<(PathBuf, usize)>; 2],
+    /// Overlay to save to instead of the files
+    pub overlay: Option<PathBuf>,
+    /// Save when ecsim quits
+    pub save_on_exit: bool,
+    /// Changes of pages written during this run, oldest first, by chip and offset
+    pub changes: Mutex<BTreeMap<(usize, usize), VecDeque<PageChange>>>,
+}
+
+impl FlashFiles {
+    pub fn new(pmem: &[u8], xmem: &[u8]) -> Self {
+        Self {
+            base: [pmem.into(), xmem.into()],
+            paths: [None, None],
+            overlay: None,
+            save_on_exit: false,
+            changes: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Record a change of a flash range at the current step, by the path and command that made it
+pub fn record(ec: &Ec, chip: usize, offset: usize, len: usize, path: &'static str, command: &'static str, pc: u16) {
+    let size = ec.flash.base[chip].len();
+    let mut changes = ec.flash.changes.lock().unwrap();
+    for page in offset / PAGE_SIZE..(offset + len + PAGE_SIZE - 1) / PAGE_SIZE {
+        let page_offset = (page * PAGE_SIZE) % size;
+        let history = changes.entry((chip, page_offset)).or_insert_with(VecDeque::new);
+        // Bytes written by the same command count as one change
+        match history.back_mut() {
+            Some(last) if last.path == path && last.command == command && last.step == ec.steps && last.pc == pc => {
+                last.writes += 1;
+            },
+            _ => {
+                if history.len() >= CHANGES_MAX {
+                    history.pop_front();
+                }
+                history.push_back(PageChange {
+                    path,
+                    command,
+                    step: ec.steps,
+                    pc,
+                    writes: 1,
+                });
+            },
+        }
+    }
+}
+
+/// Record the ranges changed by SPI commands since the last call
+pub fn record_spi(ec: &Ec, chip: usize, spi: &mut Spi, path: &'static str, pc: u16) {
+    for (offset, len, command) in spi.written.drain(..) {
+        record(ec, chip, offset, len, path, command, pc);
+    }
+}
+
+/// Pages that differ from the base images, as chip and offset
+pub fn changed_pages(ec: &Ec) -> Vec<(usize, usize)> {
+    let mcu = ec.mcu.lock().unwrap();
+    let xmem = ec.xmem.lock().unwrap();
+    let flashes: [&[u8]; 2] = [&mcu.pmem, &xmem];
+
+    let mut pages = Vec::new();
+    for (chip, flash) in flashes.iter().enumerate() {
+        let base = &ec.flash.base[chip];
+        for offset in (0..flash.len()).step_by(PAGE_SIZE) {
+            let end = (offset + PAGE_SIZE).min(flash.len());
+            if base.get(offset..end) != Some(&flash[offset..end]) {
+                pages.push((chip, offset));
+            }
+        }
+    }
+    pages
+}
+
+/// Apply the overlay, if it exists, returning the number of pages loaded
+pub fn load_overlay(ec: &Ec) -> io::Result<usize> {
+    let path = match &ec.flash.overlay {
+        Some(some) => some,
+        None => return Ok(0),
+    };
+    let data = match fs::read(path) {
+        Ok(ok) => ok,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+
+    if ! data.starts_with(OVERLAY_MAGIC) {
+        return Err(invalid("not an ecsim flash overlay"));
+    }
+
+    let mut mcu = ec.mcu.lock().unwrap();
+    let mut xmem = ec.xmem.lock().unwrap();
+    let mut pages = 0;
+    for record in data[OVERLAY_MAGIC.len()..].chunks(5 + PAGE_SIZE) {
+        if record.len() != 5 + PAGE_SIZE {
+            return Err(invalid("truncated flash overlay"));
+        }
+        let offset = u32::from_le_bytes([record[1], record[2], record[3], record[4]]) as usize;
+        let flash: &mut [u8] = match record[0] {
+            0 => &mut mcu.pmem,
+            1 => &mut xmem,
+            _ => return Err(invalid("unknown chip in flash overlay")),
+        };
+        let page = match flash.get_mut(offset..offset + PAGE_SIZE) {
+            Some(some) => some,
+            None => return Err(invalid("page outside of flash in flash overlay")),
+        };
+        page.copy_from_slice(&record[5..]);
+        pages += 1;
+    }
+    Ok(pages)
+}
+
+/// Save flash contents to the overlay, if there is one, or write them back to their files
+pub fn save(ec: &Ec) -> io::Result<()> {
+    if let Some(path) = &ec.flash.overlay {
+        let mut data = OVERLAY_MAGIC.to_vec();
+        let pages = changed_pages(ec);
+        let mcu = ec.mcu.lock().unwrap();
+        let xmem = ec.xmem.lock().unwrap();
+        let flashes: [&[u8]; 2] = [&mcu.pmem, &xmem];
+        for (chip, offset) in pages {
+            let mut page = flashes[chip][offset..].iter().copied().take(PAGE_SIZE).collect::<Vec<u8>>();
+            page.resize(PAGE_SIZE, 0xFF);
+            data.push(chip as u8);
+            data.extend_from_slice(&(offset as u32).to_le_bytes());
+            data.extend_from_slice(&page);
+        }
+        return fs::write(path, data);
+    }
+
+    if ec.flash.paths.iter().all(|path| path.is_none()) {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no flash file or overlay to save to"));
+    }
+
+    let mcu = ec.mcu.lock().unwrap();
+    let xmem = ec.xmem.lock().unwrap();
+    let flashes: [&[u8]; 2] = [&mcu.pmem, &xmem];
+    for (path, flash) in ec.flash.paths.iter().zip(flashes.iter()) {
+        if let Some((path, len)) = path {
+            fs::write(path, &flash[..(*len).min(flash.len())])?;
+        }
+    }
+    Ok(())
+}
+
+/// Restore the flash contents loaded from the images, discarding changes and any overlay
+pub fn revert(ec: &Ec) {
+    let mut mcu = ec.mcu.lock().unwrap();
+    let mut xmem = ec.xmem.lock().unwrap();
+    mcu.pmem.copy_from_slice(&ec.flash.base[0]);
+    xmem.copy_from_slice(&ec.flash.base[1]);
+    ec.flash.changes.lock().unwrap().clear();
+}
+
+/// Line of a hex dump with 16 bytes per line, as used by `diff`
+fn hex_row(flash: &[u8], row: usize) -> String {
+    let start = row * 16;
+    let mut line = format!("{:06X}:", start);
+    for value in flash[start..(start + 16).min(flash.len())].iter() {
+        let _ = write!(line, " {:02X}", value);
+    }
+    line
+}
+
+/// Differences from the base images as a unified diff between hex dumps of each chip, made of
+/// `hex_row` lines, with `a/` being the loaded image and `b/` the current contents. Line N of the
+/// dump is row N - 1, so standard tools can read and apply it
+pub fn diff(ec: &Ec) -> String {
+    let mcu = ec.mcu.lock().unwrap();
+    let xmem = ec.xmem.lock().unwrap();
+    let flashes: [&[u8]; 2] = [&mcu.pmem, &xmem];
+
+    let mut text = String::new();
+    for (chip, flash) in flashes.iter().enumerate() {
+        let base = &ec.flash.base[chip];
+        let rows = (flash.len() + 15) / 16;
+        let changed = |row: usize| {
+            let range = row * 16..(row * 16 + 16).min(flash.len());
+            base.get(range.clone()) != Some(&flash[range])
+        };
+        let changed_rows: Vec<usize> = (0..rows).filter(|&row| changed(row)).collect();
+        if changed_rows.is_empty() {
+            continue;
+        }
+
+        let _ = writeln!(text, "--- a/{}", CHIPS[chip]);
+        let _ = writeln!(text, "+++ b/{}", CHIPS[chip]);
+
+        let mut i = 0;
+        while i < changed_rows.len() {
+            // Merge changes whose context overlaps into one hunk
+            let start = changed_rows[i].saturating_sub(DIFF_CONTEXT);
+            let mut end = (changed_rows[i] + DIFF_CONTEXT + 1).min(rows);
+            i += 1;
+            while i < changed_rows.len() && changed_rows[i].saturating_sub(DIFF_CONTEXT) <= end {
+                end = (changed_rows[i] + DIFF_CONTEXT + 1).min(rows);
+                i += 1;
+            }
+
+            let _ = writeln!(text, "@@ -{},{} +{},{} @@", start + 1, end - start, start + 1, end - start);
+            let mut row = start;
+            while row < end {
+                if ! changed(row) {
+                    let _ = writeln!(text, " {}", hex_row(flash, row));
+                    row += 1;
+                    continue;
+                }
+                // Removed lines of a run of changed rows, then the added lines
+                let run_end = (row..end).find(|&row| ! changed(row)).unwrap_or(end);
+                for run_row in row..run_end {
+                    let _ = writeln!(text, "-{}", hex_row(base, run_row));
+                }
+                for run_row in row..run_end {
+                    let _ = writeln!(text, "+{}", hex_row(flash, run_row));
+                }
+                row = run_end;
+            }
+        }
+    }
+    text
+}
@@ -0,0 +1,580 @@
+// SPDX-License-Identifier: MIT
+
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use crate::ec::Ec;
+use crate::kbc;
+use crate::pmc::{PMC_REGS, STATUS_CMD, STATUS_IBF, STATUS_OBF};
+use crate::protocol::{self, Frame, Outcome, Wait};
+use crate::smfi;
+use crate::socket::socket_op;
+use crate::superio::{LDN_KEYBOARD, LDN_PMC, LDN_SMFI};
+
+#[cfg(feature = "debug_socket")]
+macro_rules! debug {
+    ($($arg:tt)*) => (eprint!($($arg)*));
+}
+
+#[cfg(not(feature = "debug_socket"))]
+macro_rules! debug {
+    ($($arg:tt)*) => (());
+}
+
+/// Steps between servicing queued host requests
+const HOST_POLL_STEPS: u64 = 1000;
+
+/// HRAMWC bit selecting I/O instead of memory cycles for H2RAM
+const HRAMWC_IO: u8 = 1 << 4;
+
+/// HRAMWxAAS bits protecting an H2RAM window from host writes and reads
+const HRAMWAAS_WRITE_PROTECT: u8 = 1 << 4;
+const HRAMWAAS_READ_PROTECT: u8 = 1 << 5;
+
+/// H2RAM windows, as HRAMWC enable bit, HRAMWxBA, and HRAMWxAAS
+const H2RAM_WINDOWS: [(u8, usize, usize); 2] = [
+    (1 << 0, 0x105B, 0x105D),
+    (1 << 1, 0x105C, 0x105E),
+];
+
+/// POST codes kept for the post command
+const POST_CODES_MAX: usize = 1024;
+
+/// SMECCS bit allowing host writes to flash, set by firmware
+const SMECCS_HOSTWA: u8 = 1 << 5;
+
+/// Time to wait for host requests between checks of execution state
+const HOST_WAIT: Duration = Duration::from_millis(10);
+
+/// Error from a host bus cycle
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HostError {
+    /// Nothing decodes the address
+    Unimplemented,
+    /// The address is decoded, but protected from the access
+    Protected,
+}
+
+/// Asynchronous event sent from the EC to the host
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Notification {
+    /// Interrupt request on the serial IRQ line
+    Irq(u8),
+    /// System control interrupt
+    Sci,
+    /// System management interrupt
+    Smi,
+    /// Virtual wire index sent by the EC, with its register value
+    VirtualWire(u8, u8),
+}
+
+/// Find the PMC channel decoding a port, returning the channel and if it is the command port
+fn pmc_port(ec: &Ec, port: u16) -> Option<(usize, bool)> {
+    for (channel, ldn) in LDN_PMC.iter().enumerate() {
+        if let Some((data, command)) = ec.superio.lock().unwrap().ports(*ldn) {
+            if port == data {
+                return Some((channel, false));
+            } else if port == command {
+                return Some((channel, true));
+            }
+        }
+    }
+    None
+}
+
+/// Check if the KBC decodes a port, returning if it is the command port
+fn kbc_port(ec: &Ec, port: u16) -> Option<bool> {
+    let (data, command) = ec.superio.lock().unwrap().ports(LDN_KEYBOARD)?;
+    if port == data {
+        Some(false)
+    } else if port == command {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// Translate a host address to EC SRAM through the H2RAM windows. Windows are aligned to their
+/// size, and decoded at their SRAM address by I/O cycles, or relative to the SMFI memory base by
+/// memory cycles
+fn h2ram(ec: &Ec, xram: &[u8], address: u32, io: bool, write: bool) -> Option<Result<usize, HostError>> {
+    let hramwc = xram[0x105A];
+    if (hramwc & HRAMWC_IO != 0) != io {
+        return None;
+    }
+
+    let host_base = if io { 0 } else { ec.superio.lock().unwrap().h2ram_base() };
+    for &(enable, ba, aas) in H2RAM_WINDOWS.iter() {
+        if hramwc & enable == 0 {
+            continue;
+        }
+
+        let length = 16u32 << (xram[aas] & 0x7);
+        let start = ((xram[ba] as u32) << 4) & !(length - 1);
+        let offset = address.wrapping_sub(host_base.wrapping_add(start));
+        if offset >= length {
+            continue;
+        }
+
+        let protect = if write { HRAMWAAS_WRITE_PROTECT } else { HRAMWAAS_READ_PROTECT };
+        if xram[aas] & protect != 0 {
+            return Some(Err(HostError::Protected));
+        }
+        return Some(Ok((start + offset) as usize));
+    }
+    None
+}
+
+/// Host I/O read cycle
+pub fn inb(ec: &mut Ec, port: u16) -> Result<u8, HostError> {
+    read_port(ec, port, true)
+}
+
+/// Read a port like a host I/O read cycle, without the side effects of reading data ports, so
+/// that OBF and the data are left for the next read
+pub fn peekb(ec: &Ec, port: u16) -> Result<u8, HostError> {
+    read_port(ec, port, false)
+}
+
+fn read_port(ec: &Ec, port: u16, consume: bool) -> Result<u8, HostError> {
+    let mut mcu = ec.mcu.lock().unwrap();
+
+    debug!(" read 0x{:04X}", port);
+    let value = if let Some((channel, command)) = pmc_port(ec, port) {
+        let regs = &PMC_REGS[channel];
+        if command {
+            debug!(" (pmc{} status)", channel + 1);
+            mcu.xram[regs.sts]
+        } else {
+            debug!(" (pmc{} data)", channel + 1);
+            if consume {
+                mcu.xram[regs.sts] &= !STATUS_OBF;
+            }
+            mcu.xram[regs.data_out]
+        }
+    } else if let Some(command) = kbc_port(ec, port) {
+        if command {
+            debug!(" (kbc status)");
+            mcu.xram[kbc::STATUS]
+        } else {
+            debug!(" (kbc data)");
+            let status = mcu.xram[kbc::STATUS];
+            if consume {
+                mcu.xram[kbc::STATUS] &= !kbc::STATUS_OBF;
+            }
+            if status & kbc::STATUS_AUX != 0 {
+                mcu.xram[kbc::DATA_MOUSE]
+            } else {
+                mcu.xram[kbc::DATA_KEYBOARD]
+            }
+        }
+    } else { match port {
+        0x2e => {
+            debug!(" (super io address)");
+            ec.superio.lock().unwrap().addr
+        },
+        0x2f => {
+            let superio = ec.superio.lock().unwrap();
+            debug!(" (super io data 0x{:02X})", superio.addr);
+            match superio.read(ec.id, ec.version) {
+                Some(value) => value,
+                None => {
+                    debug!(" (unimplemented)");
+                    return Err(HostError::Unimplemented);
+                }
+            }
+        },
+        _ => match h2ram(ec, &mcu.xram, port as u32, true, false) {
+            Some(Ok(i)) => {
+                debug!(" (h2ram 0x{:04X})", i);
+                mcu.xram[i]
+            },
+            Some(Err(err)) => {
+                debug!(" (h2ram protected)");
+                return Err(err);
+            },
+            None => {
+                debug!(" (unimplemented)");
+                return Err(HostError::Unimplemented);
+            }
+        }
+    }};
+    debug!(" = 0x{:02X}", value);
+    Ok(value)
+}
+
+/// Host I/O write cycle
+pub fn outb(ec: &mut Ec, port: u16, value: u8) -> Result<(), HostError> {
+    let mut mcu = ec.mcu.lock().unwrap();
+
+    debug!(" write 0x{:04X}, 0x{:02X}", port, value);
+    if let Some((channel, command)) = pmc_port(ec, port) {
+        let regs = &PMC_REGS[channel];
+        if command {
+            debug!(" (pmc{} command)", channel + 1);
+            mcu.xram[regs.sts] |= STATUS_CMD | STATUS_IBF;
+        } else {
+            debug!(" (pmc{} data)", channel + 1);
+            mcu.xram[regs.sts] &= !STATUS_CMD;
+            mcu.xram[regs.sts] |= STATUS_IBF;
+        }
+        mcu.xram[regs.data_in] = value;
+        return Ok(());
+    }
+
+    if let Some(command) = kbc_port(ec, port) {
+        if command {
+            debug!(" (kbc command)");
+            mcu.xram[kbc::STATUS] |= kbc::STATUS_CMD | kbc::STATUS_IBF;
+        } else {
+            debug!(" (kbc data)");
+            mcu.xram[kbc::STATUS] &= !kbc::STATUS_CMD;
+            mcu.xram[kbc::STATUS] |= kbc::STATUS_IBF;
+        }
+        mcu.xram[kbc::DATA_IN] = value;
+        return Ok(());
+    }
+
+    match port {
+        0x80 | 0x81 => {
+            debug!(" (post code)");
+            // Latch into P80HDR or P81HDR and set P80H81HS, as the EC snoops these writes
+            mcu.xram[0x2031 + (port as usize - 0x80)] = value;
+            mcu.xram[0x2030] |= 1 << 0;
+            if ec.post_codes.len() >= POST_CODES_MAX {
+                ec.post_codes.pop_front();
+            }
+            ec.post_codes.push_back((ec.steps, port, value));
+        },
+        0x2e => {
+            debug!(" (super io address)");
+            ec.superio.lock().unwrap().write_addr(value);
+        },
+        0x2f => {
+            let mut superio = ec.superio.lock().unwrap();
+            debug!(" (super io data 0x{:02X})", superio.addr);
+            if ! superio.write(value) {
+                debug!(" (unimplemented)");
+                return Err(HostError::Unimplemented);
+            }
+        },
+        _ => match h2ram(ec, &mcu.xram, port as u32, true, true) {
+            Some(Ok(i)) => {
+                debug!(" (h2ram 0x{:04X})", i);
+                mcu.xram[i] = value;
+            },
+            Some(Err(err)) => {
+                debug!(" (h2ram protected)");
+                return Err(err);
+            },
+            None => {
+                debug!(" (unimplemented)");
+                return Err(HostError::Unimplemented);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Host access to the flash through SMFI, where the flash is mapped to the top of memory. Like
+/// ECINDAR, addresses 0xFFFFFDxx send and receive SPI bytes in follow mode, and 0xFFFFFExx
+/// deselect the chip
+fn flash(ec: &mut Ec, address: u32, new_opt: Option<u8>) -> Result<u8, HostError> {
+    if ! ec.superio.lock().unwrap().active(LDN_SMFI) {
+        debug!(" (smfi inactive)");
+        return Err(HostError::Unimplemented);
+    }
+
+    let mut mcu = ec.mcu.lock().unwrap();
+    if new_opt.is_some() && mcu.xram[0x1020] & SMECCS_HOSTWA == 0 {
+        debug!(" (protected)");
+        return Err(HostError::Protected);
+    }
+
+    let mut spi = ec.spi.lock().unwrap();
+    spi.now = ec.steps;
+    if address & 0xFFFF_0000 == 0xFFFF_0000 {
+        match (address >> 8) as u8 {
+            0xFD => {
+                debug!(" (flash follow enable)");
+                let value = if let Some(new) = new_opt {
+                    spi.input.push_back(new);
+                    new
+                } else {
+                    spi.read(&mut mcu.pmem, "internal")
+                };
+                crate::flash::record_spi(ec, 0, &mut spi, "host follow", mcu.pc);
+                return Ok(value);
+            },
+            0xFE => {
+                debug!(" (flash follow disable)");
+                spi.deselect(&mut mcu.pmem, "internal");
+                crate::flash::record_spi(ec, 0, &mut spi, "host follow", mcu.pc);
+                return Ok(0xFF);
+            },
+            _ => (),
+        }
+    }
+
+    let size = smfi::flash_size(&mcu.xram, mcu.pmem.len());
+    let base = 0x1_0000_0000 - size as u64;
+    if (address as u64) < base {
+        debug!(" (unimplemented)");
+        return Err(HostError::Unimplemented);
+    }
+    let i = (address as u64 - base) as usize;
+    debug!(" (flash 0x{:06X})", i);
+    let kind = if new_opt.is_some() { smfi::PZR_HOST_WRITE } else { smfi::PZR_HOST_READ };
+    if smfi::protected(&mcu.xram, i, kind) {
+        debug!(" (protected)");
+        return Err(HostError::Protected);
+    }
+    if let Some(new) = new_opt {
+        mcu.pmem[i] = new;
+        crate::flash::record(ec, 0, i, 1, "host", "write", mcu.pc);
+    }
+    Ok(mcu.pmem[i])
+}
+
+/// Host memory read cycle, decoding H2RAM before flash
+pub fn readb(ec: &mut Ec, address: u32) -> Result<u8, HostError> {
+    debug!(" read memory 0x{:08X}", address);
+    let value = {
+        let mcu = ec.mcu.lock().unwrap();
+        match h2ram(ec, &mcu.xram, address, false, false) {
+            Some(Ok(i)) => {
+                debug!(" (h2ram 0x{:04X})", i);
+                Some(mcu.xram[i])
+            },
+            Some(Err(err)) => {
+                debug!(" (h2ram protected)");
+                return Err(err);
+            },
+            None => None,
+        }
+    };
+    let value = match value {
+        Some(some) => some,
+        None => flash(ec, address, None)?,
+    };
+    debug!(" = 0x{:02X}", value);
+    Ok(value)
+}
+
+/// Host memory write cycle, decoding H2RAM before flash
+pub fn writeb(ec: &mut Ec, address: u32, value: u8) -> Result<(), HostError> {
+    debug!(" write memory 0x{:08X}, 0x{:02X}", address, value);
+    {
+        let mut mcu = ec.mcu.lock().unwrap();
+        match h2ram(ec, &mcu.xram, address, false, true) {
+            Some(Ok(i)) => {
+                debug!(" (h2ram 0x{:04X})", i);
+                mcu.xram[i] = value;
+                return Ok(());
+            },
+            Some(Err(err)) => {
+                debug!(" (h2ram protected)");
+                return Err(err);
+            },
+            None => (),
+        }
+    }
+    flash(ec, address, Some(value))?;
+    Ok(())
+}
+
+/// Message from a host interface thread to the execution thread
+pub enum HostMessage {
+    /// Legacy UDP request
+    Legacy {
+        request: [u8; 4],
+        addr: SocketAddr,
+    },
+    /// Framed protocol client connected
+    Connect {
+        client: usize,
+        writer: Box<dyn Write + Send>,
+    },
+    /// Framed protocol request
+    Frame {
+        client: usize,
+        frame: Frame,
+    },
+    /// Framed protocol client disconnected
+    Disconnect {
+        client: usize,
+    },
+}
+
+/// Deterministic host timing. Host operations are applied at step boundaries spaced by the host
+/// bus latency, and execution waits for the next operation instead of running ahead of the host.
+/// Only virtual time is used, so however long the host takes between operations, they are applied
+/// at the same steps
+pub struct Deterministic {
+    /// Steps between host operations
+    pub latency: u64,
+}
+
+/// Queue of host requests, answered by the execution thread
+pub struct Host {
+    messages: Receiver<HostMessage>,
+    udp_opt: Option<UdpSocket>,
+    clients: HashMap<usize, Box<dyn Write + Send>>,
+    waits: Vec<Wait>,
+    deterministic_opt: Option<Deterministic>,
+    /// Operations received but not yet applied
+    pending: VecDeque<HostMessage>,
+    /// Execution waits for the host at next_time
+    lockstep: bool,
+    /// Step at which the next operation is applied in lockstep
+    next_time: u64,
+}
+
+impl Host {
+    pub fn new(messages: Receiver<HostMessage>, udp_opt: Option<UdpSocket>, deterministic_opt: Option<Deterministic>) -> Self {
+        Self {
+            messages,
+            udp_opt,
+            clients: HashMap::new(),
+            waits: Vec::new(),
+            // Virtual time starts with the first host operation in deterministic mode
+            lockstep: deterministic_opt.is_some(),
+            deterministic_opt,
+            pending: VecDeque::new(),
+            next_time: 0,
+        }
+    }
+
+    /// Steps between servicing requests
+    pub fn poll_steps(&self) -> u64 {
+        match &self.deterministic_opt {
+            Some(deterministic) => deterministic.latency.max(1),
+            None => HOST_POLL_STEPS,
+        }
+    }
+
+    fn send(&mut self, client: usize, frame: &Frame) {
+        if let Some(writer) = self.clients.get_mut(&client) {
+            if let Err(err) = frame.to_bytes().and_then(|bytes| writer.write_all(&bytes)) {
+                eprintln!("host client {}: failed to write: {}", client, err);
+                self.clients.remove(&client);
+                self.waits.retain(|wait| wait.client != client);
+            }
+        }
+    }
+
+    fn handle(&mut self, ec: &mut Ec, message: HostMessage) {
+        match message {
+            HostMessage::Legacy { request, addr } => {
+                let response = socket_op(ec, &request);
+                if let Some(udp) = &self.udp_opt {
+                    if let Err(err) = udp.send_to(&response, addr) {
+                        eprintln!("failed to write socket: {}", err);
+                    }
+                }
+            },
+            HostMessage::Connect { client, writer } => {
+                self.clients.insert(client, writer);
+            },
+            HostMessage::Frame { client, frame } => {
+                match protocol::execute(ec, client, &frame) {
+                    Outcome::Done(response) => self.send(client, &response),
+                    Outcome::Wait(wait) => self.waits.push(wait),
+                }
+            },
+            HostMessage::Disconnect { client } => {
+                self.clients.remove(&client);
+                self.waits.retain(|wait| wait.client != client);
+            },
+        }
+    }
+
+    /// Accept a message, queueing operations until they are applied
+    fn receive(&mut self, ec: &mut Ec, message: HostMessage) {
+        match message {
+            HostMessage::Connect { .. } | HostMessage::Disconnect { .. } => self.handle(ec, message),
+            _ => self.pending.push_back(message),
+        }
+    }
+
+    /// Answer waits that are satisfied or timed out
+    fn check_waits(&mut self, ec: &mut Ec) {
+        let mut i = 0;
+        while i < self.waits.len() {
+            if let Some(response) = self.waits[i].check(ec) {
+                let wait = self.waits.remove(i);
+                self.send(wait.client, &response);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Send notifications raised by the EC to all framed protocol clients
+    fn notify(&mut self, ec: &mut Ec) {
+        let notifications: Vec<Notification> = ec.notifications.lock().unwrap().drain(..).collect();
+        for notification in notifications {
+            let frame = protocol::notification(notification);
+            let clients: Vec<usize> = self.clients.keys().copied().collect();
+            for client in clients {
+                self.send(client, &frame);
+            }
+        }
+    }
+
+    /// Apply queued requests
+    pub fn service(&mut self, ec: &mut Ec) {
+        while let Ok(message) = self.messages.try_recv() {
+            self.receive(ec, message);
+        }
+
+        if self.deterministic_opt.is_some() {
+            // One operation per poll, so that operations are spaced by the host bus latency
+            if let Some(message) = self.pending.pop_front() {
+                self.handle(ec, message);
+                self.lockstep = true;
+                self.next_time = ec.steps + self.poll_steps();
+            }
+        } else {
+            while let Some(message) = self.pending.pop_front() {
+                self.handle(ec, message);
+            }
+        }
+
+        self.check_waits(ec);
+        self.notify(ec);
+    }
+
+    /// Check if execution has to wait for the next host operation to stay in lockstep
+    pub fn blocked(&self, ec: &Ec) -> bool {
+        self.lockstep &&
+        self.pending.is_empty() &&
+        // Clients with outstanding waits are waiting for execution
+        self.waits.is_empty() &&
+        ec.steps >= self.next_time
+    }
+
+    /// Wait for the next host operation in lockstep. Execution stays blocked however long the
+    /// host is idle, until lockstep is turned off with `Runner::set_lockstep`
+    pub fn wait_lockstep(&mut self, ec: &Mutex<Ec>) {
+        if let Ok(message) = self.messages.recv_timeout(HOST_WAIT) {
+            let mut ec = ec.lock().unwrap();
+            self.receive(&mut ec, message);
+        }
+    }
+
+    /// Wait for requests while execution is paused, then apply them
+    pub fn wait(&mut self, ec: &Mutex<Ec>) {
+        if ! self.pending.is_empty() {
+            self.service(&mut ec.lock().unwrap());
+        } else if let Ok(message) = self.messages.recv_timeout(HOST_WAIT) {
+            let mut ec = ec.lock().unwrap();
+            self.receive(&mut ec, message);
+            self.service(&mut ec);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+// SPDX-License-Identifier: MIT
+
+//! Firmware images for the internal and external flash, built from raw binaries, Intel HEX, or
+//! ELF files. ELF files are detected by their magic, Intel HEX files by the `.hex` or `.ihx`
+//! extension, and anything else is a raw binary, loaded at offset 0 unless given as
+//! `PATH@OFFSET` (in hex).
+//! Intel HEX files ending with `.ihx` use SDCC bank addressing, where address A of bank N is
+//! linked at `N << 16 | A` and stored in flash at `N * 0x8000 + A`, for A from 0x8000 to 0xFFFF.
+//! ELF files are loaded by the physical address of their loadable segments.
+
+use std::fs;
+use std::path::Path;
+
+/// EC chip that can be simulated
+pub struct Chip {
+    pub name: &'static str,
+    pub id: u16,
+    pub version: u8,
+    /// Size of program memory
+    pub flash_size: usize,
+    /// Flags byte of the signature written by our firmware for this chip
+    pub signature_flags: u8,
+}
+
+pub static CHIPS: [Chip; 2] = [
+    // IT5570 (B Version)
+    Chip { name: "it5570", id: 0x5570, version: 0x01, flash_size: 128 * 1024, signature_flags: 0x85 },
+    // IT8587E/VG (F Version)
+    Chip { name: "it8587", id: 0x8587, version: 0x06, flash_size: 128 * 1024, signature_flags: 0x94 },
+];
+
+/// Start of the signature the boot code searches for at 16 byte boundaries, followed by the
+/// flags byte
+const SIGNATURE: [u8; 6] = [0xA5; 6];
+
+/// Code banks, as name, start, and end
+const BANKS: [(&str, usize, usize); 4] = [
+    ("common", 0x00000, 0x08000),
+    ("bank 0", 0x08000, 0x10000),
+    ("bank 1", 0x10000, 0x18000),
+    ("bank 2", 0x18000, 0x20000),
+];
+
+/// Find a chip by name
+pub fn chip(name: &str) -> Option<&'static Chip> {
+    CHIPS.iter().find(|chip| chip.name == name)
+}
+
+/// Data loaded at an offset in flash
+pub struct Segment {
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+/// Split a `PATH@OFFSET` spec into path and offset
+fn parse_spec(spec: &str) -> Result<(&str, Option<usize>), String> {
+    match spec.rsplit_once('@') {
+        Some((path, offset)) => {
+            let offset = offset.trim_start_matches("0x");
+            match usize::from_str_radix(offset, 16) {
+                Ok(ok) => Ok((path, Some(ok))),
+                Err(err) => Err(format!("invalid offset in '{}': {}", spec, err)),
+            }
+        },
+        None => Ok((spec, None)),
+    }
+}
+
+/// Check if a spec is a raw binary at offset 0, which can be written back
+pub fn is_raw(spec: &str) -> bool {
+    match parse_spec(spec) {
+        Ok((path, None)) => match fs::read(path) {
+            Ok(data) => ! is_elf(&data) && ! is_hex(path),
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(b"\x7FELF")
+}
+
+/// Intel HEX is only detected by extension, as raw binaries can start with ':'
+fn is_hex(path: &str) -> bool {
+    let extension = Path::new(path).extension().and_then(|x| x.to_str()).unwrap_or("");
+    matches!(extension, "ihx" | "hex")
+}
+
+/// Load the segments of an image spec
+pub fn load(spec: &str) -> Result<Vec<Segment>, String> {
+    let (path, offset_opt) = parse_spec(spec)?;
+    let data = fs::read(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+
+    if let Some(offset) = offset_opt {
+        return Ok(vec![Segment { offset, data }]);
+    }
+
+    let result = if is_elf(&data) {
+        parse_elf(&data)
+    } else if is_hex(path) {
+        let sdcc = path.ends_with(".ihx");
+        parse_hex(&data, sdcc)
+    } else {
+        Ok(vec![Segment { offset: 0, data }])
+    };
+    result.map_err(|err| format!("{}: {}", path, err))
+}
+
+/// Build a flash image of a size from image specs, filled with 0xFF. Also returns the end of the
+/// loaded data, which is where the image ends before padding
+pub fn build(specs: &[String], size: usize) -> Result<(Vec<u8>, usize), String> {
+    let mut image = vec![0xFF; size];
+    let mut loaded = 0;
+    for spec in specs.iter() {
+        for segment in load(spec)? {
+            let end = segment.offset + segment.data.len();
+            if end > size {
+                return Err(format!(
+                    "{}: data at 0x{:X} to 0x{:X} does not fit in {} KiB of flash",
+                    spec,
+                    segment.offset,
+                    end,
+                    size / 1024
+                ));
+            }
+            image[segment.offset..end].copy_from_slice(&segment.data);
+            loaded = loaded.max(end);
+        }
+    }
+    Ok((image, loaded))
+}
+
+fn parse_hex(data: &[u8], sdcc: bool) -> Result<Vec<Segment>, String> {
+    let text = String::from_utf8_lossy(data);
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut upper = 0;
+    for (line_i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = |message: &str| format!("line {}: {}", line_i + 1, message);
+        let hex = line.strip_prefix(':').ok_or_else(|| error("missing ':'"))?;
+        if hex.len() % 2 != 0 || hex.len() < 10 {
+            return Err(error("invalid record length"));
+        }
+        let mut bytes = Vec::with_capacity(hex.len() / 2);
+        for i in (0..hex.len()).step_by(2) {
+            let byte = u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| error("invalid hex"))?;
+            bytes.push(byte);
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("invalid checksum"));
+        }
+
+        let len = bytes[0] as usize;
+        if bytes.len() != len + 5 {
+            return Err(error("invalid record length"));
+        }
+        let address = (bytes[1] as usize) << 8 | bytes[2] as usize;
+        let record = &bytes[4..4 + len];
+        match bytes[3] {
+            // Data
+            0x00 => {
+                let linear = upper + address;
+                let offset = if sdcc && linear >= 0x10000 {
+                    let bank = linear >> 16;
+                    let address = linear & 0xFFFF;
+                    if address < 0x8000 {
+                        return Err(error("banked data outside of 0x8000 to 0xFFFF"));
+                    }
+                    bank * 0x8000 + address
+                } else {
+                    linear
+                };
+
+                // Extend the last segment if contiguous
+                match segments.last_mut() {
+                    Some(last) if last.offset + last.data.len() == offset => {
+                        last.data.extend_from_slice(record);
+                    },
+                    _ => segments.push(Segment { offset, data: record.to_vec() }),
+                }
+            },
+            // End of file
+            0x01 => break,
+            // Extended segment address
+            0x02 if len == 2 => {
+                upper = ((record[0] as usize) << 8 | record[1] as usize) << 4;
+            },
+            // Extended linear address
+            0x04 if len == 2 => {
+                upper = ((record[0] as usize) << 8 | record[1] as usize) << 16;
+            },
+            // Start addresses
+            0x03 | 0x05 => (),
+            kind => return Err(error(&format!("unsupported record type 0x{:02X}", kind))),
+        }
+    }
+    Ok(segments)
+}
+
+fn parse_elf(data: &[u8]) -> Result<Vec<Segment>, String> {
+    let error = || "truncated ELF file".to_string();
+    let class64 = match data.get(4) {
+        Some(1) => false,
+        Some(2) => true,
+        _ => return Err("invalid ELF class".to_string()),
+    };
+    let big_endian = match data.get(5) {
+        Some(1) => false,
+        Some(2) => true,
+        _ => return Err("invalid ELF data encoding".to_string()),
+    };
+
+    let read = |offset: usize, size: usize| -> Result<usize, String> {
+        let bytes = data.get(offset..offset + size).ok_or_else(error)?;
+        let fold = |value: usize, byte: &u8| value << 8 | *byte as usize;
+        Ok(if big_endian {
+            bytes.iter().fold(0, fold)
+        } else {
+            bytes.iter().rev().fold(0, fold)
+        })
+    };
+
+    // Header fields and program header fields, as offset and size
+    let (phoff, phentsize, phnum) = if class64 {
+        ((0x20, 8), (0x36, 2), (0x38, 2))
+    } else {
+        ((0x1C, 4), (0x2A, 2), (0x2C, 2))
+    };
+    let (p_offset, p_paddr, p_filesz) = if class64 {
+        ((0x08, 8), (0x18, 8), (0x20, 8))
+    } else {
+        ((0x04, 4), (0x0C, 4), (0x10, 4))
+    };
+
+    let phoff = read(phoff.0, phoff.1)?;
+    let phentsize = read(phentsize.0, phentsize.1)?;
+    let phnum = read(phnum.0, phnum.1)?;
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let header = phoff + i * phentsize;
+        // PT_LOAD
+        if read(header, 4)? != 1 {
+            continue;
+        }
+        let offset = read(header + p_offset.0, p_offset.1)?;
+        let paddr = read(header + p_paddr.0, p_paddr.1)?;
+        let filesz = read(header + p_filesz.0, p_filesz.1)?;
+        if filesz == 0 {
+            continue;
+        }
+        let segment_data = data.get(offset..offset + filesz).ok_or_else(error)?;
+        segments.push(Segment {
+            offset: paddr,
+            data: segment_data.to_vec(),
+        });
+    }
+    Ok(segments)
+}
+
+/// Result of checking an internal flash image before booting
+pub struct Validation {
+    pub summary: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Check an internal flash image like the boot code expects it: signature, reset vector, size,
+/// and content of each bank. `len` is where the loaded image ends, before padding to the flash
+/// size. Fails if the image does not fit in the chip's flash
+pub fn validate(chip: &Chip, image: &[u8], len: usize) -> Result<Validation, String> {
+    if len > chip.flash_size {
+        return Err(format!(
+            "image of {} bytes is larger than the {} KiB flash of {}",
+            len,
+            chip.flash_size / 1024,
+            chip.name
+        ));
+    }
+    let image = &image[..len.min(image.len())];
+
+    let mut summary = Vec::new();
+    let mut warnings = Vec::new();
+
+    let used = image.iter().rposition(|&b| b != 0xFF).map_or(0, |i| i + 1);
+    summary.push(format!(
+        "{}: {} KiB flash, image ends at 0x{:05X}, {} bytes used",
+        chip.name,
+        chip.flash_size / 1024,
+        len,
+        used
+    ));
+    if used == 0 {
+        warnings.push("image is erased".to_string());
+    }
+
+    // Signature
+    let signature_opt = (0..image.len().saturating_sub(SIGNATURE.len()))
+        .step_by(16)
+        .find(|&i| image[i..].starts_with(&SIGNATURE));
+    match signature_opt {
+        Some(offset) => {
+            let flags = image.get(offset + SIGNATURE.len()).copied().unwrap_or(0xFF);
+            summary.push(format!("signature at 0x{:05X}, flags 0x{:02X}", offset, flags));
+            if flags != chip.signature_flags {
+                warnings.push(format!(
+                    "signature flags 0x{:02X} do not match 0x{:02X} used for {}",
+                    flags,
+                    chip.signature_flags,
+                    chip.name
+                ));
+            }
+        },
+        None => warnings.push("no signature found, the boot code will not run this image".to_string()),
+    }
+
+    // Reset vector
+    match image.get(0..3) {
+        Some(&[0x02, high, low]) => {
+            let target = (high as usize) << 8 | low as usize;
+            summary.push(format!("reset vector LJMP 0x{:04X}", target));
+            if target >= image.len() {
+                warnings.push(format!(
+                    "reset vector jumps to 0x{:04X}, past the end of the image at 0x{:05X}",
+                    target,
+                    image.len()
+                ));
+            } else if image[target] == 0xFF {
+                warnings.push(format!("reset vector jumps to erased flash at 0x{:04X}", target));
+            }
+        },
+        Some(&[0xFF, ..]) | None => warnings.push("reset vector is erased".to_string()),
+        Some(&[opcode, ..]) => summary.push(format!("reset vector opcode 0x{:02X}", opcode)),
+    }
+
+    // Banks
+    let mut banks = Vec::new();
+    let mut empty_bank_opt = None;
+    for &(name, start, end) in BANKS.iter() {
+        let bank = match image.get(start..end.min(image.len())) {
+            Some(some) if ! some.is_empty() => some,
+            _ => {
+                banks.push(format!("{} not loaded", name));
+                continue;
+            },
+        };
+        let bytes = bank.iter().filter(|&&b| b != 0xFF).count();
+        banks.push(format!("{} {}", name, bytes));
+        if bytes == 0 {
+            empty_bank_opt.get_or_insert(name);
+        } else if let Some(empty_bank) = empty_bank_opt {
+            warnings.push(format!("{} is empty but {} is used, the image may be truncated", empty_bank, name));
+            empty_bank_opt = None;
+        }
+    }
+    summary.push(format!("bytes used by bank: {}", banks.join(", ")));
+
+    Ok(Validation { summary, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Intel HEX record with its checksum
+    fn record(kind: u8, address: u16, data: &[u8]) -> String {
+        let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+        bytes.extend_from_slice(data);
+        let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+        bytes.push(checksum);
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!(":{}\n", hex.concat())
+    }
+
+    #[test]
+    fn hex_sdcc_banks() {
+        let mut text = record(0x00, 0x0000, &[0x02, 0x00, 0x10]);
+        // Bank 2, linked at 0x2_8000
+        text += &record(0x04, 0x0000, &[0x00, 0x02]);
+        text += &record(0x00, 0x8000, &[0xAA, 0xBB]);
+        text += &record(0x01, 0x0000, &[]);
+
+        let segments = parse_hex(text.as_bytes(), true).unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].offset, 0x0000);
+        assert_eq!(segments[0].data, [0x02, 0x00, 0x10]);
+        assert_eq!(segments[1].offset, 2 * 0x8000 + 0x8000);
+        assert_eq!(segments[1].data, [0xAA, 0xBB]);
+
+        // Without SDCC banking, addresses are linear
+        let segments = parse_hex(text.as_bytes(), false).unwrap();
+        assert_eq!(segments[1].offset, 0x2_8000);
+    }
+
+    #[test]
+    fn hex_bad_checksum() {
+        let mut text = record(0x00, 0x0000, &[0x12]);
+        text.replace_range(text.len() - 3.., "00\n");
+        assert!(parse_hex(text.as_bytes(), false).is_err());
+    }
+
+    #[test]
+    fn elf_load_segment_at_offset() {
+        // 32-bit little endian ELF header, with one program header after it
+        let mut elf = vec![0; 52 + 32];
+        elf[0..6].copy_from_slice(&[0x7F, b'E', b'L', b'F', 1, 1]);
+        elf[0x1C..0x20].copy_from_slice(&52u32.to_le_bytes());
+        elf[0x2A..0x2C].copy_from_slice(&32u16.to_le_bytes());
+        elf[0x2C..0x2E].copy_from_slice(&1u16.to_le_bytes());
+
+        // PT_LOAD of 4 bytes at the end of the file, to physical address 0x1_8000
+        let header = 52;
+        elf[header..header + 4].copy_from_slice(&1u32.to_le_bytes());
+        elf[header + 0x04..header + 0x08].copy_from_slice(&(84u32).to_le_bytes());
+        elf[header + 0x08..header + 0x0C].copy_from_slice(&0x8000u32.to_le_bytes());
+        elf[header + 0x0C..header + 0x10].copy_from_slice(&0x1_8000u32.to_le_bytes());
+        elf[header + 0x10..header + 0x14].copy_from_slice(&4u32.to_le_bytes());
+        elf.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+
+        assert!(is_elf(&elf));
+        let segments = parse_elf(&elf).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].offset, 0x1_8000);
+        assert_eq!(segments[0].data, [0xDE, 0xAD, 0xBE, 0xEF]);
+    }
+
+    #[test]
+    fn validate_rejects_image_larger_than_chip() {
+        let chip = chip("it5570").unwrap();
+        let image = vec![0x00; chip.flash_size + 16];
+        assert!(validate(chip, &image, image.len()).is_err());
+        assert!(validate(chip, &image, chip.flash_size).is_ok());
+    }
+
+    #[test]
+    fn validate_warns_on_short_image() {
+        let chip = chip("it5570").unwrap();
+
+        // Reset vector jumps past the end of the image, which ends in bank 0
+        let mut image = vec![0xFF; chip.flash_size];
+        image[0..3].copy_from_slice(&[0x02, 0x90, 0x00]);
+        image[0x9000] = 0x00;
+        let validation = validate(chip, &image, 0x8100).unwrap();
+        assert!(validation.warnings.iter().any(|warning| warning.contains("past the end of the image")));
+        assert!(validation.summary.iter().any(|line| line.contains("bank 1 not loaded")));
+
+        // The same target is found when the image is long enough
+        let validation = validate(chip, &image, image.len()).unwrap();
+        assert!(! validation.warnings.iter().any(|warning| warning.contains("past the end of the image")));
+    }
+
+    #[test]
+    fn hex_detected_by_extension() {
+        assert!(is_hex("ec.ihx"));
+        assert!(is_hex("ec.hex"));
+        // Raw binaries starting with ':' stay raw
+        assert!(! is_hex("ec.rom"));
+    }
+}
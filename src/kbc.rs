@@ -0,0 +1,18 @@
+// SPDX-License-Identifier: MIT
+
+// The system flag (bit 2) and other status bits are set by firmware, and passed to the host as is
+
+pub const STATUS_OBF: u8 = 1 << 0;
+pub const STATUS_IBF: u8 = 1 << 1;
+pub const STATUS_CMD: u8 = 1 << 3;
+/// Output buffer holds mouse data
+pub const STATUS_AUX: u8 = 1 << 5;
+
+/// Host interface status, KBHISR
+pub const STATUS: usize = 0x1304;
+/// Keyboard data output, KBHIKDOR
+pub const DATA_KEYBOARD: usize = 0x1306;
+/// Mouse data output, KBHIMDOR
+pub const DATA_MOUSE: usize = 0x1308;
+/// Data input, KBHIDIR
+pub const DATA_IN: usize = 0x130A;
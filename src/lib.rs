@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: MIT
+
+//! Simulator for System76 EC firmware, built on the area8051 emulator. The `ecsim` binary serves
+//! the simulated EC to host tools, with [`Runner`] executing it on its own thread, and [`Sim`]
+//! drives it in-process.
+
+pub mod client;
+
+pub use self::ec::Ec;
+mod ec;
+
+pub mod espi;
+
+mod event;
+pub mod flash;
+
+pub mod host;
+
+pub mod image;
+
+pub mod kbc;
+
+pub mod pmc;
+
+pub mod protocol;
+
+pub use self::run::Runner;
+mod run;
+
+pub use self::sim::Sim;
+mod sim;
+
+mod smfi;
+
+pub mod socket;
+
+pub use self::spi::Spi;
+pub mod spi;
+
+pub mod superio;
+
+mod timer;
+
+pub use self::uart::Uart;
+pub mod uart;
+
+pub use self::xram::xram;
+mod xram;
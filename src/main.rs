@@ -1,24 +1,16 @@
 // SPDX-License-Identifier: MIT
 
-use area8051::{Addr, Isa, Mem};
-use std::{env, fs, io};
+use std::{env, fs, io, thread};
 use std::collections::{BTreeMap, HashMap};
-use std::net::UdpSocket;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::{TcpListener, UdpSocket};
+use std::sync::{Arc, mpsc};
 
-pub (crate) mod cmd;
+use ecsim::{Ec, Runner, Spi, flash, image, pmc, protocol, spi, superio};
+use ecsim::host::{Deterministic, Host};
+use ecsim::socket::socket_thread;
+use ecsim::uart::uart_io;
 
-pub use self::ec::Ec;
-mod ec;
-
-use self::socket::socket_op;
-mod socket;
-
-pub use self::spi::Spi;
-mod spi;
-
-pub use self::xram::xram;
-mod xram;
+mod cmd;
 
 type CommandMap = HashMap<&'static str, Box<dyn Fn(&mut Ec, &[&str])>>;
 
@@ -38,11 +30,7 @@ impl<'a> liner::Completer for Completer<'a> {
     }
 }
 
-static QUIT: AtomicBool = AtomicBool::new(false);
-static RUNNING: AtomicBool = AtomicBool::new(true);
-static STEP: AtomicBool = AtomicBool::new(false);
-
-fn commands() -> CommandMap {
+fn commands(runner: &Arc<Runner>) -> CommandMap {
     let mut commands: CommandMap = HashMap::new();
     let mut command_help = BTreeMap::new();
 
@@ -53,9 +41,15 @@ fn commands() -> CommandMap {
         });
     }
 
-    command!("continue", "continue execution", |_, _| {
+    let continue_runner = runner.clone();
+    command!("continue", "continue execution", move |_, _| {
         eprintln!("continuing...");
-        RUNNING.store(true, Ordering::SeqCst);
+        continue_runner.set_running(true);
+    });
+    let stop_runner = runner.clone();
+    command!("stop", "pause execution", move |_, _| {
+        eprintln!("stopping...");
+        stop_runner.set_running(false);
     });
     command!("echo", "print arguments", |_, args: &[&str]| {
         for (i, arg) in args.iter().enumerate() {
@@ -66,18 +60,28 @@ fn commands() -> CommandMap {
         }
         eprintln!();
     });
-    command!("quit", "quit program", |_, _| {
+    let quit_runner = runner.clone();
+    command!("quit", "quit program", move |_, _| {
         eprintln!("quiting...");
-        QUIT.store(true, Ordering::SeqCst);
+        quit_runner.quit();
     });
-    command!("step", "execute one instruction", |ec: &mut Ec, _| {
+    let step_runner = runner.clone();
+    command!("step", "execute one instruction", move |ec: &mut Ec, _| {
         let mcu = ec.mcu.lock().unwrap();
         eprintln!("step: {:04X}", mcu.pc);
-        STEP.store(true, Ordering::SeqCst);
+        step_runner.step();
     });
     command!("steps", "number of instructions executed", |ec: &mut Ec, _| {
         eprintln!("steps: {}", ec.steps);
     });
+    let lockstep_runner = runner.clone();
+    command!("lockstep", "wait for the host with --deterministic (on or off)", move |_, args: &[&str]| {
+        match args.get(0) {
+            Some(&"on") => lockstep_runner.set_lockstep(true),
+            Some(&"off") => lockstep_runner.set_lockstep(false),
+            _ => eprintln!("lockstep requires on or off"),
+        }
+    });
 
     command!("iram", "dump internal RAM", |ec: &mut Ec, _| {
         let mcu = ec.mcu.lock().unwrap();
@@ -106,7 +110,7 @@ fn commands() -> CommandMap {
                 },
             };
 
-            eprintln!("xram {:04X}: {:02X}", addr, mcu.xram[addr]);
+            eprintln!("xram {:04X}: {:02X}", addr, mcu.xram[ec.sram(addr)]);
 
             if let Some(arg1) = args.get(1) {
                 let value = match u8::from_str_radix(arg1, 16) {
@@ -117,9 +121,9 @@ fn commands() -> CommandMap {
                     },
                 };
 
-                mcu.xram[addr] = value;
+                mcu.xram[ec.sram(addr)] = value;
 
-                eprintln!("xram {:04X}: {:02X}", addr, mcu.xram[addr]);
+                eprintln!("xram {:04X}: {:02X}", addr, mcu.xram[ec.sram(addr)]);
             }
         } else {
             eprintln!("xram:");
@@ -127,7 +131,7 @@ fn commands() -> CommandMap {
                 let row_offset = row * 16;
                 eprint!("{:04X}:", row_offset);
                 for col in 0..16 {
-                    eprint!(" {:02X}", mcu.xram[row_offset + col]);
+                    eprint!(" {:02X}", mcu.xram[ec.sram(row_offset + col)]);
                 }
                 eprintln!();
             }
@@ -141,9 +145,20 @@ fn commands() -> CommandMap {
     command!("kbc_mouse", "read kbc mouse data (as hex)", cmd::kbc::mouse);
     command!("kbc_write", "send kbc data (one argument in hex)", cmd::kbc::write);
 
-    command!("pmc_cmd", "send pmc command (one argument in hex)", cmd::pmc::cmd);
-    command!("pmc_read", "read pmc data (as hex)", cmd::pmc::read);
-    command!("pmc_write", "send pmc data (one argument in hex)", cmd::pmc::write);
+    command!("pmc_cmd", "send pmc command (argument in hex, optional channel 1 to 5)", cmd::pmc::cmd);
+    command!("pmc_read", "read pmc data (as hex, optional channel 1 to 5)", cmd::pmc::read);
+    command!("pmc_write", "send pmc data (argument in hex, optional channel 1 to 5)", cmd::pmc::write);
+
+    command!("post", "show POST codes written by the host (clear to reset)", cmd::post::post);
+
+    command!("espi", "show eSPI configuration, or set it (offset and value in hex) or reset it as the host", cmd::espi::espi);
+    command!("vw", "show eSPI virtual wires, or send one from the host (name and 0 or 1)", cmd::vw::vw);
+
+    command!("sci", "show SCIs raised by firmware (clear to reset)", cmd::sci::sci);
+    command!("flash", "show changed flash pages, save them, revert to the loaded images, list writes (changes), or diff against the images", cmd::flash::flash);
+    command!("spi", "show SPI flash status and protocol errors (clear to reset)", cmd::spi::spi);
+
+    command!("uart_write", "send line to uart (arguments joined by spaces), the only input with --uart stdio", cmd::uart::write);
 
     command_help.insert("help", "show command information");
     commands.insert("help", Box::new(move |_, args: &[&str]| {
@@ -157,152 +172,228 @@ fn commands() -> CommandMap {
     commands
 }
 
-fn timers(ec: &mut Ec) {
-    // Timer information from https://openlabpro.com/guide/timers-8051/
-    let mut tcon = ec.load(Addr::Reg(0x88));
-    let tmod = ec.load(Addr::Reg(0x89));
-
-    // Timer 0 running
-    if tcon & 1 << 4 != 0 {
-        assert!(tmod & 0x0F == 0x01, "unimplemented TMOD 0x{:02X}", tmod);
-
-        if ec.steps % 12 == 0 {
-            let tl = 0x8A;
-            let th = 0x8C;
-
-            let mut count =
-                ec.load(Addr::Reg(tl)) as u16 |
-                (ec.load(Addr::Reg(th)) as u16) << 8;
-
-            count = count.wrapping_add(1);
-
-            if count == 0 {
-                tcon |= 1 << 5;
-                //TODO: implement timer 0 interrupts
+fn main() {
+    let mut chip = &image::CHIPS[0];
+    let mut internal_specs = Vec::new();
+    let mut external_specs = Vec::new();
+    let mut uart_spec = "stdio".to_string();
+    let mut host_tcp = "127.0.0.1:8588".to_string();
+    let mut host_unix_opt = None;
+    let mut deterministic_opt = None;
+    let mut pmc_ports = Vec::new();
+    let mut flash_devices = Vec::new();
+    let mut flash_busy_opt = None;
+    let mut flash_save = false;
+    let mut flash_overlay_opt = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--uart" => {
+                uart_spec = args.next().expect("--uart requires stdio (output only, input with uart_write), file:OUT[,IN], tcp:PORT, or pty");
+            },
+            "--host-tcp" => {
+                host_tcp = args.next().expect("--host-tcp requires an address");
+            },
+            "--host-unix" => {
+                host_unix_opt = Some(args.next().expect("--host-unix requires a path"));
+            },
+            "--deterministic" => {
+                let arg = args.next().expect("--deterministic requires LATENCY");
+                let latency = arg.parse::<u64>().expect("invalid host latency");
+                deterministic_opt = Some(Deterministic { latency });
+            },
+            "--pmc" => {
+                let arg = args.next().expect("--pmc requires CHANNEL:DATA,COMMAND");
+                let (channel, ports) = pmc::parse_ports(&arg).expect("invalid pmc ports");
+                pmc_ports.push((channel, ports));
+            },
+            "--flash-device" => {
+                let arg = args.next().expect("--flash-device requires [internal:|external:]DEVICE");
+                let (external, name) = match arg.split_once(':') {
+                    Some(("internal", name)) => (false, name.to_string()),
+                    Some(("external", name)) => (true, name.to_string()),
+                    Some(_) => panic!("invalid flash '{}'", arg),
+                    None => (true, arg),
+                };
+                let device = spi::device(&name).unwrap_or_else(|| {
+                    let names: Vec<&str> = spi::DEVICES.iter().map(|device| device.name).collect();
+                    panic!("unknown flash device '{}', expected one of {}", name, names.join(", "))
+                });
+                flash_devices.push((external, device));
+            },
+            "--flash-busy" => {
+                let arg = args.next().expect("--flash-busy requires PROGRAM,ERASE");
+                let (program, erase) = arg.split_once(',').expect("--flash-busy requires PROGRAM,ERASE");
+                flash_busy_opt = Some((
+                    program.parse::<u64>().expect("invalid flash program time"),
+                    erase.parse::<u64>().expect("invalid flash erase time"),
+                ));
+            },
+            "--flash-save" => {
+                flash_save = true;
+            },
+            "--flash-overlay" => {
+                flash_overlay_opt = Some(args.next().expect("--flash-overlay requires a path"));
+            },
+            "--chip" => {
+                let arg = args.next().expect("--chip requires a chip name");
+                chip = image::chip(&arg).unwrap_or_else(|| {
+                    let names: Vec<&str> = image::CHIPS.iter().map(|chip| chip.name).collect();
+                    panic!("unknown chip '{}', expected one of {}", arg, names.join(", "))
+                });
+            },
+            "--external" => {
+                external_specs.push(args.next().expect("--external requires an image"));
+            },
+            _ => {
+                internal_specs.push(arg);
             }
-
-            ec.store(Addr::Reg(tl), count as u8);
-            ec.store(Addr::Reg(th), (count >> 8) as u8);
         }
     }
 
-    // Timer 1 running
-    if tcon & 1 << 6 != 0 {
-        assert!(tmod & 0xF0 == 0x10, "unimplemented TMOD 0x{:02X}", tmod);
-
-        if ec.steps % 12 == 0 {
-            let tl = 0x8B;
-            let th = 0x8D;
-
-            let mut count =
-                ec.load(Addr::Reg(tl)) as u16 |
-                (ec.load(Addr::Reg(th)) as u16) << 8;
-
-            count = count.wrapping_add(1);
+    if internal_specs.is_empty() {
+        internal_specs.push("ec.rom".to_string());
+    }
 
-            if count == 0 {
-                tcon |= 1 << 7;
-                //TODO: implement timer 1 interrupts
+    let (pmem, mut internal_len) = image::build(&internal_specs, chip.flash_size)
+        .unwrap_or_else(|err| panic!("failed to load internal flash: {}", err));
+    let (xmem, external_len) = if external_specs.is_empty() {
+        (pmem.clone(), internal_len)
+    } else {
+        let device = flash_devices.iter()
+            .rev()
+            .find(|(external, _)| *external)
+            .map_or(&spi::W25Q16JV, |(_, device)| *device);
+        image::build(&external_specs, device.size)
+            .unwrap_or_else(|err| panic!("failed to load external flash: {}", err))
+    };
+
+    let mut ec = Ec::from_images(chip.id, chip.version, pmem, xmem);
+
+    // Only single raw images can be written back, at the length they were loaded with
+    let images = [(&internal_specs, internal_len), (&external_specs, external_len)];
+    for (path, (specs, len)) in ec.flash.paths.iter_mut().zip(images.iter()) {
+        if let [spec] = specs.as_slice() {
+            if image::is_raw(spec) {
+                *path = Some((spec.into(), *len));
             }
-
-            ec.store(Addr::Reg(tl), count as u8);
-            ec.store(Addr::Reg(th), (count >> 8) as u8);
+        }
+    }
+    if flash_save && ec.flash.paths.iter().all(|path| path.is_none()) {
+        eprintln!("--flash-save requires a raw image, use --flash-overlay instead");
+        flash_save = false;
+    }
+    ec.flash.save_on_exit = flash_save || flash_overlay_opt.is_some();
+    if let Some(flash_overlay) = flash_overlay_opt {
+        ec.flash.overlay = Some(flash_overlay.into());
+        match flash::load_overlay(&ec) {
+            Ok(0) => (),
+            Ok(pages) => {
+                eprintln!("loaded {} flash pages from overlay", pages);
+                // Overlay pages can be anywhere in flash
+                internal_len = chip.flash_size;
+            },
+            Err(err) => panic!("failed to load flash overlay: {}", err),
         }
     }
 
-    ec.store(Addr::Reg(0x88), tcon);
-}
-
-fn main() {
-    ctrlc::set_handler(|| {
-        RUNNING.store(false, Ordering::SeqCst);
-    }).expect("failed to set ctrl-c handler");
-
-    let pmem_path = env::args().nth(1).unwrap_or_else(|| "ec.rom".to_string());
-
-    let mut pmem = fs::read(&pmem_path).expect("failed to read ec.rom");
+    // Check the image that boots, including any overlay
+    let validation = image::validate(chip, &ec.mcu.lock().unwrap().pmem, internal_len)
+        .unwrap_or_else(|err| panic!("invalid internal flash: {}", err));
+    for line in validation.summary.iter() {
+        eprintln!("image: {}", line);
+    }
+    for warning in validation.warnings.iter() {
+        eprintln!("image warning: {}", warning);
+    }
 
-    // Fill program memory to 128 KiB
-    while pmem.len() < 128 * 1024 {
-        pmem.push(0xFF);
+    for (external, device) in flash_devices {
+        let spi = if external { &ec.spi_external } else { &ec.spi };
+        *spi.lock().unwrap() = Spi::new(device);
     }
 
-    let xmem = pmem.clone();
+    if let Some((program, erase)) = flash_busy_opt {
+        for spi in [&ec.spi, &ec.spi_external].iter() {
+            let mut spi = spi.lock().unwrap();
+            spi.program_time = program;
+            spi.erase_time = erase;
+        }
+    }
 
-    let mut ec = Ec::new(
-        0x5570, 0x01, // IT5570 (B Version)
-        //0x8587, 0x06, // IT8587E/VG (F Version)
-        pmem.into_boxed_slice(),
-        xmem.into_boxed_slice()
-    );
+    for (channel, ports) in pmc_ports {
+        ec.superio.lock().unwrap().set_ports(superio::LDN_PMC[channel], ports);
+    }
 
-    ec.reset();
+    ec.uart.lock().unwrap().io = uart_io(&uart_spec).expect("failed to open uart");
 
-    let commands = commands();
+    let (messages, receiver) = mpsc::channel();
 
-    let mut socket_opt = UdpSocket::bind("127.0.0.1:8587").ok();
-    if let Some(ref mut socket) = socket_opt {
-        socket.set_nonblocking(true).expect("failed to set socket nonblocking");
+    let udp_opt = match UdpSocket::bind("127.0.0.1:8587") {
+        Ok(socket) => {
+            let reply_socket = socket.try_clone().expect("failed to clone socket");
+            let messages = messages.clone();
+            thread::spawn(move || socket_thread(socket, messages));
+            Some(reply_socket)
+        },
+        Err(err) => {
+            eprintln!("failed to bind socket: {}", err);
+            None
+        }
+    };
+
+    match TcpListener::bind(&host_tcp) {
+        Ok(listener) => {
+            let messages = messages.clone();
+            thread::spawn(move || protocol::tcp_thread(listener, messages));
+        },
+        Err(err) => {
+            eprintln!("failed to bind host interface {}: {}", host_tcp, err);
+        }
     }
 
-    let mut con = liner::Context::new();
-    while ! QUIT.load(Ordering::SeqCst) {
-        while STEP.swap(false, Ordering::SeqCst) || RUNNING.load(Ordering::SeqCst) {
-            if let Some(ref mut socket) = socket_opt {
-                let mut request = [0x00; 4];
-                match socket.recv_from(&mut request) {
-                    Ok((count, addr)) => if count >= request.len() {
-                        let response = socket_op(&mut ec, &request);
-                        socket.send_to(&response, addr).expect("failed to write socket");
-                    },
-                    Err(err) => match err.kind() {
-                        io::ErrorKind::WouldBlock => (),
-                        io::ErrorKind::Interrupted => {
-                            eprintln!("^C");
-                        },
-                        io::ErrorKind::UnexpectedEof => {
-                            eprintln!("^D");
-                            QUIT.store(true, Ordering::SeqCst);
-                        },
-                        _ => {
-                            panic!("failed to read socket: {:?}", err);
-                        }
-                    }
-                }
-            }
-
-            ec.step();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
 
-            // Check pcon for idle or power down
-            let pcon = ec.load(Addr::Reg(0x87));
-            if (pcon & 0b11) != 0 {
-                //panic!("unimplemented PCON 0x{:02X}", pcon);
+        if let Some(host_unix) = host_unix_opt {
+            // Remove socket left behind by a previous run
+            if let Ok(metadata) = fs::metadata(&host_unix) {
+                if metadata.file_type().is_socket() {
+                    let _ = fs::remove_file(&host_unix);
+                }
             }
-
-            // Serial bus
-            let s = ec.load(Addr::Reg(0x98));
-            let b = ec.load(Addr::Reg(0x99));
-            if b > 0 {
-                print!("{}", b as char);
-                ec.store(Addr::Reg(0x98), s | (1 << 1));
-                ec.store(Addr::Reg(0x99), 0);
+            match std::os::unix::net::UnixListener::bind(&host_unix) {
+                Ok(listener) => {
+                    let messages = messages.clone();
+                    thread::spawn(move || protocol::unix_thread(listener, messages));
+                },
+                Err(err) => {
+                    eprintln!("failed to bind host interface {}: {}", host_unix, err);
+                }
             }
+        }
+    }
 
+    let host_opt = Some(Host::new(receiver, udp_opt, deterministic_opt));
 
-            timers(&mut ec);
+    let runner = Arc::new(Runner::new(ec));
 
-            // if ec.steps % 1_000_000 == 0 {
-            //     println!("{}M steps", ec.steps / 1_000_000);
-            // }
+    {
+        let runner = runner.clone();
+        ctrlc::set_handler(move || {
+            runner.set_running(false);
+        }).expect("failed to set ctrl-c handler");
+    }
 
-            if ec.pc() == 0 {
-                eprintln!("reset!");
-                //RUNNING.store(false, Ordering::SeqCst);
-            }
+    let commands = commands(&runner);
 
-            ec.steps += 1;
-        }
+    let run_thread = {
+        let runner = runner.clone();
+        thread::spawn(move || runner.run(host_opt))
+    };
 
+    let mut con = liner::Context::new();
+    while ! runner.quitting() {
         match con.read_line(
             liner::Prompt::from("[ecsim]$ "),
             None,
@@ -315,7 +406,8 @@ fn main() {
                 if let Some(command) = parts.next() {
                     if let Some(func) = commands.get(command) {
                         let args: Vec<&str> = parts.collect();
-                        func(&mut ec, &args);
+                        // Execution is paused while the command holds the lock
+                        func(&mut runner.lock(), &args);
                     } else {
                         eprintln!("unknown command: {}", ok);
                     }
@@ -326,10 +418,11 @@ fn main() {
             Err(err) => match err.kind() {
                 io::ErrorKind::Interrupted => {
                     eprintln!("^C");
+                    runner.set_running(false);
                 },
                 io::ErrorKind::UnexpectedEof => {
                     eprintln!("^D");
-                    QUIT.store(true, Ordering::SeqCst);
+                    runner.quit();
                 },
                 _ => {
                     panic!("error: {:?}", err);
@@ -337,4 +430,13 @@ fn main() {
             }
         }
     }
+
+    run_thread.join().expect("failed to join execution thread");
+
+    let ec = runner.lock();
+    if ec.flash.save_on_exit {
+        if let Err(err) = flash::save(&ec) {
+            eprintln!("failed to save flash: {}", err);
+        }
+    }
 }
@@ -0,0 +1,44 @@
+// SPDX-License-Identifier: MIT
+
+pub const STATUS_OBF: u8 = 1 << 0;
+pub const STATUS_IBF: u8 = 1 << 1;
+pub const STATUS_CMD: u8 = 1 << 3;
+
+/// EC side registers of a PMC channel, as XRAM addresses
+pub struct PmcRegs {
+    /// Status, PMxSTS
+    pub sts: usize,
+    /// Data output to the host, PMxDO
+    pub data_out: usize,
+    /// Data input from the host, PMxDI
+    pub data_in: usize,
+}
+
+pub const PMC_REGS: [PmcRegs; 5] = [
+    PmcRegs { sts: 0x1500, data_out: 0x1501, data_in: 0x1504 },
+    PmcRegs { sts: 0x1510, data_out: 0x1511, data_in: 0x1514 },
+    PmcRegs { sts: 0x1520, data_out: 0x1521, data_in: 0x1522 },
+    PmcRegs { sts: 0x1530, data_out: 0x1531, data_in: 0x1532 },
+    PmcRegs { sts: 0x1540, data_out: 0x1541, data_in: 0x1542 },
+];
+
+/// Parse host ports of a channel from a description like `4:6b,6f` or `4:none`, in hex
+pub fn parse_ports(spec: &str) -> Result<(usize, Option<(u16, u16)>), String> {
+    let mut parts = spec.splitn(2, ':');
+    let channel = match parts.next().unwrap_or("").parse::<usize>() {
+        Ok(channel @ 1 ..= 5) => channel - 1,
+        _ => return Err(format!("invalid pmc channel in '{}'", spec)),
+    };
+    let ports = parts.next().ok_or_else(|| format!("missing pmc ports in '{}'", spec))?;
+    if ports == "none" {
+        return Ok((channel, None));
+    }
+    let mut ports = ports.splitn(2, ',');
+    let mut port = || -> Result<u16, String> {
+        let port = ports.next().unwrap_or("");
+        u16::from_str_radix(port, 16).map_err(|err| format!("invalid pmc port '{}': {}", port, err))
+    };
+    let data = port()?;
+    let command = port()?;
+    Ok((channel, Some((data, command))))
+}
@@ -0,0 +1,434 @@
+// SPDX-License-Identifier: MIT
+
+//! Framed host interface protocol, served over TCP or a Unix socket.
+//!
+//! All values are little endian. Every frame starts with a header:
+//!
+//! | Offset | Size | Field                               |
+//! |--------|------|-------------------------------------|
+//! | 0      | 1    | version, currently 1                |
+//! | 1      | 1    | kind                                |
+//! | 2      | 2    | id, copied from request to response |
+//! | 4      | 2    | payload length                      |
+//!
+//! Responses use the request kind with `RESPONSE` set, and their payload starts with a status
+//! byte. Notifications are sent with kind `NOTIFY`, id 0, and a payload of source and data, which
+//! is the IRQ for `NOTIFY_IRQ`, 0 for `NOTIFY_SCI` and `NOTIFY_SMI`, and the index and register
+//! value for `NOTIFY_VW`.
+//!
+//! Request payloads:
+//!
+//! - `HELLO`: none, responds with the protocol version
+//! - `INB`, `INW`, `INL`: port (u16), responds with the value read
+//! - `OUTB`, `OUTW`, `OUTL`: port (u16) and value
+//! - `BATCH`: sequence of kind (u8), length (u8), and payload. Responds with a sequence of status
+//!   (u8), length (u8), and data. Execution stops at the first error, which becomes the status of
+//!   the batch
+//! - `WAIT`: port (u16), mask (u8), value (u8), timeout in steps (u32). Responds once the port
+//!   read masked equals value, or with `TIMEOUT`. The port is read without side effects, so data
+//!   ports keep their data and OBF
+//! - `MEMR`: address (u32) and length (u8), responds with the bytes read by memory cycles
+//! - `MEMW`: address (u32) and bytes to write with memory cycles
+//! - `VWGET`: eSPI virtual wire index (u8), responds with the index register value
+//! - `VWSET`: eSPI virtual wire index (u8) and value, with valid bits 7:4 selecting the wires to
+//!   set to the levels in bits 3:0. Only indexes driven by the host can be set
+//! - `GETCFG`: eSPI configuration offset (u16), responds with the register (u32)
+//! - `SETCFG`: eSPI configuration offset (u16) and register (u32), as SET_CONFIGURATION
+//! - `ESPIRST`: none, asserts eSPI reset
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use crate::ec::Ec;
+use crate::espi;
+use crate::host::{self, HostError, HostMessage, Notification};
+
+pub const VERSION: u8 = 1;
+
+pub const HELLO: u8 = 0x00;
+pub const INB: u8 = 0x01;
+pub const INW: u8 = 0x02;
+pub const INL: u8 = 0x03;
+pub const OUTB: u8 = 0x04;
+pub const OUTW: u8 = 0x05;
+pub const OUTL: u8 = 0x06;
+pub const BATCH: u8 = 0x07;
+pub const WAIT: u8 = 0x08;
+pub const MEMR: u8 = 0x09;
+pub const MEMW: u8 = 0x0A;
+pub const VWGET: u8 = 0x0B;
+pub const VWSET: u8 = 0x0C;
+pub const GETCFG: u8 = 0x0D;
+pub const SETCFG: u8 = 0x0E;
+pub const ESPIRST: u8 = 0x0F;
+pub const NOTIFY: u8 = 0x7F;
+pub const RESPONSE: u8 = 0x80;
+
+pub const STATUS_OK: u8 = 0x00;
+pub const STATUS_UNIMPLEMENTED: u8 = 0x01;
+pub const STATUS_BAD_REQUEST: u8 = 0x02;
+pub const STATUS_TIMEOUT: u8 = 0x03;
+pub const STATUS_BAD_VERSION: u8 = 0x04;
+pub const STATUS_PROTECTED: u8 = 0x05;
+
+/// Largest payload that fits in the length field of a frame
+pub const PAYLOAD_MAX: usize = u16::MAX as usize;
+
+pub const NOTIFY_IRQ: u8 = 0x01;
+pub const NOTIFY_SCI: u8 = 0x02;
+pub const NOTIFY_SMI: u8 = 0x03;
+pub const NOTIFY_VW: u8 = 0x04;
+
+impl From<HostError> for u8 {
+    fn from(err: HostError) -> u8 {
+        match err {
+            HostError::Unimplemented => STATUS_UNIMPLEMENTED,
+            HostError::Protected => STATUS_PROTECTED,
+        }
+    }
+}
+
+/// Protocol frame, without the payload length which is implied
+pub struct Frame {
+    pub version: u8,
+    pub kind: u8,
+    pub id: u16,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Read one frame from a stream
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut header = [0; 6];
+        reader.read_exact(&mut header)?;
+        let length = u16::from_le_bytes([header[4], header[5]]);
+        let mut payload = vec![0; length as usize];
+        reader.read_exact(&mut payload)?;
+        Ok(Self {
+            version: header[0],
+            kind: header[1],
+            id: u16::from_le_bytes([header[2], header[3]]),
+            payload,
+        })
+    }
+
+    /// Encode the frame, failing if the payload is longer than `PAYLOAD_MAX`
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let id = self.id.to_le_bytes();
+        let length = match u16::try_from(self.payload.len()) {
+            Ok(ok) => ok.to_le_bytes(),
+            Err(_) => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("frame payload of {} bytes is longer than {}", self.payload.len(), PAYLOAD_MAX)
+            )),
+        };
+        let mut bytes = vec![self.version, self.kind, id[0], id[1], length[0], length[1]];
+        bytes.extend_from_slice(&self.payload);
+        Ok(bytes)
+    }
+
+    /// Create response to this frame
+    fn response(&self, status: u8, data: &[u8]) -> Self {
+        let mut payload = vec![status];
+        payload.extend_from_slice(data);
+        Self {
+            version: VERSION,
+            kind: self.kind | RESPONSE,
+            id: self.id,
+            payload,
+        }
+    }
+}
+
+/// Create notification frame
+pub fn notification(notification: Notification) -> Frame {
+    let payload = match notification {
+        Notification::Irq(irq) => vec![NOTIFY_IRQ, irq],
+        Notification::Sci => vec![NOTIFY_SCI, 0],
+        Notification::Smi => vec![NOTIFY_SMI, 0],
+        Notification::VirtualWire(index, value) => vec![NOTIFY_VW, index, value],
+    };
+    Frame {
+        version: VERSION,
+        kind: NOTIFY,
+        id: 0,
+        payload,
+    }
+}
+
+/// Request waiting for a port to reach a value
+pub struct Wait {
+    pub client: usize,
+    request: Frame,
+    port: u16,
+    mask: u8,
+    value: u8,
+    deadline: u64,
+}
+
+impl Wait {
+    /// Get the response if the wait is satisfied or timed out
+    pub fn check(&self, ec: &Ec) -> Option<Frame> {
+        // Peek, so waiting on a data port leaves the data for the client to read
+        match host::peekb(ec, self.port) {
+            Ok(value) => if value & self.mask == self.value {
+                Some(self.request.response(STATUS_OK, &[value]))
+            } else if ec.steps >= self.deadline {
+                Some(self.request.response(STATUS_TIMEOUT, &[value]))
+            } else {
+                None
+            },
+            Err(err) => Some(self.request.response(err.into(), &[])),
+        }
+    }
+}
+
+/// Result of executing a request
+pub enum Outcome {
+    Done(Frame),
+    Wait(Wait),
+}
+
+fn port(payload: &[u8]) -> Result<u16, u8> {
+    match payload {
+        [a, b, ..] => Ok(u16::from_le_bytes([*a, *b])),
+        _ => Err(STATUS_BAD_REQUEST),
+    }
+}
+
+/// Execute memory cycles, returning the data to respond with
+fn mem(ec: &mut Ec, kind: u8, payload: &[u8]) -> Result<Vec<u8>, u8> {
+    let address = match payload {
+        [a, b, c, d, ..] => u32::from_le_bytes([*a, *b, *c, *d]),
+        _ => return Err(STATUS_BAD_REQUEST),
+    };
+
+    match kind {
+        MEMR => {
+            let length = *payload.get(4).ok_or(STATUS_BAD_REQUEST)?;
+            let mut data = Vec::with_capacity(length as usize);
+            for i in 0..length {
+                data.push(host::readb(ec, address.wrapping_add(i as u32))?);
+            }
+            Ok(data)
+        },
+        _ => {
+            for (i, value) in payload[4..].iter().enumerate() {
+                host::writeb(ec, address.wrapping_add(i as u32), *value)?;
+            }
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Execute an eSPI operation, returning the data to respond with
+fn espi_op(ec: &mut Ec, kind: u8, payload: &[u8]) -> Result<Vec<u8>, u8> {
+    match (kind, payload) {
+        (VWGET, [index]) => Ok(vec![espi::vw_get(ec, *index)?]),
+        (VWSET, [index, value]) => {
+            espi::vw_set(ec, *index, *value)?;
+            Ok(Vec::new())
+        },
+        (GETCFG, [a, b]) => {
+            let value = espi::get_configuration(ec, u16::from_le_bytes([*a, *b]))?;
+            Ok(value.to_le_bytes().to_vec())
+        },
+        (SETCFG, [a, b, c, d, e, f]) => {
+            let value = u32::from_le_bytes([*c, *d, *e, *f]);
+            espi::set_configuration(ec, u16::from_le_bytes([*a, *b]), value)?;
+            Ok(Vec::new())
+        },
+        (ESPIRST, []) => {
+            espi::espi_reset(ec)?;
+            Ok(Vec::new())
+        },
+        _ => Err(STATUS_BAD_REQUEST),
+    }
+}
+
+/// Execute a single I/O operation, returning the data to respond with
+fn io(ec: &mut Ec, kind: u8, payload: &[u8]) -> Result<Vec<u8>, u8> {
+    match kind {
+        MEMR | MEMW => return mem(ec, kind, payload),
+        VWGET | VWSET | GETCFG | SETCFG | ESPIRST => return espi_op(ec, kind, payload),
+        _ => (),
+    }
+
+    let port = port(payload)?;
+    let size = match kind {
+        INB | OUTB => 1,
+        INW | OUTW => 2,
+        INL | OUTL => 4,
+        _ => return Err(STATUS_BAD_REQUEST),
+    };
+
+    // Wider cycles are split into byte cycles, as done on LPC
+    match kind {
+        INB | INW | INL => {
+            let mut data = Vec::with_capacity(size);
+            for i in 0..size {
+                data.push(host::inb(ec, port.wrapping_add(i as u16))?);
+            }
+            Ok(data)
+        },
+        _ => {
+            let values = payload.get(2..2 + size).ok_or(STATUS_BAD_REQUEST)?;
+            for (i, value) in values.iter().enumerate() {
+                host::outb(ec, port.wrapping_add(i as u16), *value)?;
+            }
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Execute a batch of I/O operations, returning the status and data to respond with
+fn batch(ec: &mut Ec, mut payload: &[u8]) -> (u8, Vec<u8>) {
+    let mut data = Vec::new();
+    while ! payload.is_empty() {
+        let (kind, length) = match payload {
+            [kind, length, ..] => (*kind, *length as usize),
+            _ => return (STATUS_BAD_REQUEST, data),
+        };
+        let op = match payload.get(2..2 + length) {
+            Some(op) => op,
+            None => return (STATUS_BAD_REQUEST, data),
+        };
+        payload = &payload[2 + length..];
+
+        match io(ec, kind, op) {
+            Ok(result) => {
+                data.push(STATUS_OK);
+                data.push(result.len() as u8);
+                data.extend_from_slice(&result);
+            },
+            Err(status) => {
+                data.push(status);
+                data.push(0);
+                return (status, data);
+            }
+        }
+    }
+    (STATUS_OK, data)
+}
+
+/// Execute request from client
+pub fn execute(ec: &mut Ec, client: usize, request: &Frame) -> Outcome {
+    if request.version != VERSION {
+        return Outcome::Done(request.response(STATUS_BAD_VERSION, &[VERSION]));
+    }
+
+    let response = match request.kind {
+        HELLO => request.response(STATUS_OK, &[VERSION]),
+        INB | INW | INL | OUTB | OUTW | OUTL | MEMR | MEMW |
+        VWGET | VWSET | GETCFG | SETCFG | ESPIRST => match io(ec, request.kind, &request.payload) {
+            Ok(data) => request.response(STATUS_OK, &data),
+            Err(status) => request.response(status, &[]),
+        },
+        BATCH => {
+            let (status, data) = batch(ec, &request.payload);
+            request.response(status, &data)
+        },
+        WAIT => match request.payload.as_slice() {
+            [a, b, mask, value, t0, t1, t2, t3] => {
+                let timeout = u32::from_le_bytes([*t0, *t1, *t2, *t3]);
+                let wait = Wait {
+                    client,
+                    request: Frame {
+                        version: request.version,
+                        kind: request.kind,
+                        id: request.id,
+                        payload: Vec::new(),
+                    },
+                    port: u16::from_le_bytes([*a, *b]),
+                    mask: *mask,
+                    value: *value,
+                    deadline: ec.steps + timeout as u64,
+                };
+                match wait.check(ec) {
+                    Some(response) => response,
+                    None => return Outcome::Wait(wait),
+                }
+            },
+            _ => request.response(STATUS_BAD_REQUEST, &[]),
+        },
+        _ => request.response(STATUS_BAD_REQUEST, &[]),
+    };
+
+    // Batches of reads can produce more data than a frame holds
+    if response.payload.len() > PAYLOAD_MAX {
+        return Outcome::Done(request.response(STATUS_BAD_REQUEST, &[]));
+    }
+    Outcome::Done(response)
+}
+
+static NEXT_CLIENT: AtomicUsize = AtomicUsize::new(0);
+
+/// Read frames from a connected client and queue them for the execution thread
+fn connection<S: Read + Write + Send + 'static>(mut reader: S, writer: S, messages: Sender<HostMessage>) {
+    let client = NEXT_CLIENT.fetch_add(1, Ordering::SeqCst);
+    let connect = HostMessage::Connect {
+        client,
+        writer: Box::new(writer),
+    };
+    if messages.send(connect).is_err() {
+        return;
+    }
+
+    while let Ok(frame) = Frame::read(&mut reader) {
+        if messages.send(HostMessage::Frame { client, frame }).is_err() {
+            return;
+        }
+    }
+
+    let _ = messages.send(HostMessage::Disconnect { client });
+}
+
+/// Accept TCP clients
+pub fn tcp_thread(listener: TcpListener, messages: Sender<HostMessage>) {
+    for stream_res in listener.incoming() {
+        let stream = match stream_res {
+            Ok(ok) => ok,
+            Err(err) => {
+                eprintln!("failed to accept host client: {}", err);
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        let writer = match stream.try_clone() {
+            Ok(ok) => ok,
+            Err(err) => {
+                eprintln!("failed to clone host client: {}", err);
+                continue;
+            }
+        };
+        let messages = messages.clone();
+        thread::spawn(move || connection(stream, writer, messages));
+    }
+}
+
+/// Accept Unix socket clients
+#[cfg(unix)]
+pub fn unix_thread(listener: std::os::unix::net::UnixListener, messages: Sender<HostMessage>) {
+    for stream_res in listener.incoming() {
+        let stream = match stream_res {
+            Ok(ok) => ok,
+            Err(err) => {
+                eprintln!("failed to accept host client: {}", err);
+                continue;
+            }
+        };
+        let writer = match stream.try_clone() {
+            Ok(ok) => ok,
+            Err(err) => {
+                eprintln!("failed to clone host client: {}", err);
+                continue;
+            }
+        };
+        let messages = messages.clone();
+        thread::spawn(move || connection(stream, writer, messages));
+    }
+}
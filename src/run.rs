@@ -0,0 +1,208 @@
+// SPDX-License-Identifier: MIT
+
+use area8051::{Addr, Isa, Mem};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::Ec;
+use crate::event::Event;
+use crate::host::Host;
+use crate::timer::{timers, timers_running};
+use crate::uart::uart;
+
+/// Steps executed before releasing the Ec lock, so that other threads can pause execution
+const BATCH_STEPS: u64 = 10_000;
+
+/// Time to wait for host requests while paused
+const PAUSED_WAIT: Duration = Duration::from_millis(10);
+
+fn handle_event(ec: &mut Ec, event: Event, host_opt: &mut Option<Host>) {
+    match event {
+        Event::HostPoll => {
+            if let Some(host) = host_opt {
+                host.service(ec);
+                ec.events.lock().unwrap().schedule(ec.steps + host.poll_steps(), Event::HostPoll);
+            }
+        },
+        Event::MachineCycle => {
+            let (timer1, timer2) = timers(ec);
+
+            // Serial port
+            let uart_busy = uart(ec, timer1, timer2);
+
+            if uart_busy || timers_running(ec) {
+                ec.events.lock().unwrap().schedule_once(ec.steps + 12, Event::MachineCycle);
+            }
+        },
+    }
+}
+
+/// Schedule the events that start peripherals, before the first step
+pub (crate) fn start(ec: &Ec) {
+    let mut events = ec.events.lock().unwrap();
+    events.schedule(ec.steps, Event::HostPoll);
+    events.schedule_once(ec.steps, Event::MachineCycle);
+}
+
+/// Handle due events and execute one instruction
+pub (crate) fn step(ec: &mut Ec, host_opt: &mut Option<Host>) {
+    // Handle peripheral events that are due
+    loop {
+        let event_opt = ec.events.lock().unwrap().pop(ec.steps);
+        match event_opt {
+            Some(event) => handle_event(ec, event, host_opt),
+            None => break,
+        }
+    }
+
+    // Check pcon for idle or power down
+    let pcon = ec.load(Addr::Reg(0x87));
+    if (pcon & 0b10) != 0 {
+        //panic!("unimplemented PCON 0x{:02X}", pcon);
+    }
+    if (pcon & 0b01) != 0 {
+        // Skip idle time until the next event. Events wake the CPU, as peripheral
+        // interrupts are not yet delivered
+        let next_opt = ec.events.lock().unwrap().next_time();
+        if let Some(next) = next_opt {
+            ec.steps = ec.steps.max(next);
+        }
+        ec.store(Addr::Reg(0x87), pcon & !0b01);
+        return;
+    }
+
+    ec.check_reti();
+    ec.step();
+
+    // if ec.steps % 1_000_000 == 0 {
+    //     println!("{}M steps", ec.steps / 1_000_000);
+    // }
+
+    if ec.pc() == 0 {
+        eprintln!("reset!");
+        //runner.set_running(false);
+    }
+
+    ec.steps += 1;
+}
+
+/// Simulated EC executed on its own thread, with the requests other threads make to pause, step,
+/// or stop it. Each simulation has its own, so several can run in one process
+pub struct Runner {
+    ec: Mutex<Ec>,
+    /// Stop the execution thread
+    quit: AtomicBool,
+    /// Execution thread runs freely, instead of pausing
+    running: AtomicBool,
+    /// Execution thread executes one instruction while paused
+    step: AtomicBool,
+    /// Execution waits for the host in deterministic mode, instead of running ahead of it
+    lockstep: AtomicBool,
+    /// Threads waiting in `lock`, which the execution thread yields the Ec lock to. `Mutex` is
+    /// not fair, so without this the execution thread could take the lock back before they wake
+    pending: AtomicUsize,
+}
+
+impl Runner {
+    pub fn new(ec: Ec) -> Self {
+        Self {
+            ec: Mutex::new(ec),
+            quit: AtomicBool::new(false),
+            running: AtomicBool::new(true),
+            step: AtomicBool::new(false),
+            lockstep: AtomicBool::new(true),
+            pending: AtomicUsize::new(0),
+        }
+    }
+
+    /// Lock the Ec from another thread, pausing execution at the end of the current step
+    pub fn lock(&self) -> MutexGuard<'_, Ec> {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        let guard = self.ec.lock().unwrap();
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+        guard
+    }
+
+    /// Stop the execution thread
+    pub fn quit(&self) {
+        self.quit.store(true, Ordering::SeqCst);
+    }
+
+    pub fn quitting(&self) -> bool {
+        self.quit.load(Ordering::SeqCst)
+    }
+
+    /// Run freely, or pause
+    pub fn set_running(&self, running: bool) {
+        self.running.store(running, Ordering::SeqCst);
+    }
+
+    /// Execute one instruction while paused
+    pub fn step(&self) {
+        self.step.store(true, Ordering::SeqCst);
+    }
+
+    /// Wait for the host in deterministic mode, or run freely between host operations. Lockstep
+    /// is only left when asked, as leaving it on a timer would make runs depend on wall clock time
+    pub fn set_lockstep(&self, lockstep: bool) {
+        self.lockstep.store(lockstep, Ordering::SeqCst);
+    }
+
+    /// Let threads waiting in `lock` take the Ec before the execution thread locks it again
+    fn yield_pending(&self) {
+        while self.pending.load(Ordering::SeqCst) > 0 {
+            thread::yield_now();
+        }
+    }
+
+    /// Execution thread, until `quit`. The Ec is locked while running a batch of steps, so
+    /// locking it from another thread with `lock` pauses execution at an instruction boundary
+    pub fn run(&self, mut host_opt: Option<Host>) {
+        let ec = &self.ec;
+        start(&ec.lock().unwrap());
+
+        while ! self.quitting() {
+            self.yield_pending();
+
+            if self.step.swap(false, Ordering::SeqCst) {
+                step(&mut ec.lock().unwrap(), &mut host_opt);
+            } else if self.running.load(Ordering::SeqCst) {
+                let mut blocked = false;
+                {
+                    let mut ec = ec.lock().unwrap();
+                    for _ in 0..BATCH_STEPS {
+                        if ! self.running.load(Ordering::Relaxed) || self.pending.load(Ordering::Relaxed) > 0 {
+                            break;
+                        }
+                        if let Some(host) = &host_opt {
+                            if self.lockstep.load(Ordering::Relaxed) && host.blocked(&ec) {
+                                blocked = true;
+                                break;
+                            }
+                        }
+                        step(&mut ec, &mut host_opt);
+                    }
+                }
+
+                if blocked {
+                    if let Some(host) = &mut host_opt {
+                        // Wait for the host without holding the lock
+                        host.wait_lockstep(ec);
+                    }
+                }
+            } else if let Some(host) = &mut host_opt {
+                // Host requests are answered while paused
+                host.wait(ec);
+            } else {
+                thread::sleep(PAUSED_WAIT);
+            }
+        }
+    }
+
+    /// Take the Ec back once the execution thread has stopped
+    pub fn into_inner(self) -> Ec {
+        self.ec.into_inner().unwrap()
+    }
+}
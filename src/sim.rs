@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: MIT
+
+use crate::Ec;
+use crate::host::{self, HostError, Notification};
+use crate::run::{start, step};
+
+/// Simulated EC driven in-process, without threads or sockets. Host cycles go through the same
+/// logic as the host interface, and execution only advances when asked to
+pub struct Sim {
+    pub ec: Ec,
+}
+
+impl Sim {
+    pub fn new(ec: Ec) -> Self {
+        start(&ec);
+        Self { ec }
+    }
+
+    /// Create a simulated IT5570 running a ROM
+    pub fn from_rom(rom: Vec<u8>) -> Self {
+        Self::new(Ec::from_rom(0x5570, 0x01, rom))
+    }
+
+    /// Number of steps executed
+    pub fn steps(&self) -> u64 {
+        self.ec.steps
+    }
+
+    /// Execute a number of steps
+    pub fn run(&mut self, steps: u64) {
+        for _ in 0..steps {
+            step(&mut self.ec, &mut None);
+        }
+    }
+
+    /// Execute until the condition is true, checking it before each step, for at most a number of
+    /// steps. Returns false if the condition was never true
+    pub fn run_until<F: FnMut(&mut Ec) -> bool>(&mut self, max_steps: u64, mut f: F) -> bool {
+        for _ in 0..max_steps {
+            if f(&mut self.ec) {
+                return true;
+            }
+            step(&mut self.ec, &mut None);
+        }
+        f(&mut self.ec)
+    }
+
+    /// Execute until a port read masked equals a value, for at most a number of steps. The port
+    /// is read before each step, so it is usually a status port
+    pub fn wait_port(&mut self, port: u16, mask: u8, value: u8, max_steps: u64) -> Result<bool, HostError> {
+        for _ in 0..max_steps {
+            if self.inb(port)? & mask == value {
+                return Ok(true);
+            }
+            step(&mut self.ec, &mut None);
+        }
+        Ok(self.inb(port)? & mask == value)
+    }
+
+    /// Host I/O read cycle
+    pub fn inb(&mut self, port: u16) -> Result<u8, HostError> {
+        host::inb(&mut self.ec, port)
+    }
+
+    /// Host I/O write cycle
+    pub fn outb(&mut self, port: u16, value: u8) -> Result<(), HostError> {
+        host::outb(&mut self.ec, port, value)
+    }
+
+    /// Host memory read cycle
+    pub fn readb(&mut self, address: u32) -> Result<u8, HostError> {
+        host::readb(&mut self.ec, address)
+    }
+
+    /// Host memory write cycle
+    pub fn writeb(&mut self, address: u32, value: u8) -> Result<(), HostError> {
+        host::writeb(&mut self.ec, address, value)
+    }
+
+    /// Take the notifications sent to the host since the last call
+    pub fn notifications(&mut self) -> Vec<Notification> {
+        self.ec.notifications.lock().unwrap().drain(..).collect()
+    }
+}
@@ -0,0 +1,44 @@
+// SPDX-License-Identifier: MIT
+
+//! SMFI flash access control: the flash size decoded by FMSSR, and the protect regions that
+//! block host reads and writes and EC writes through ECINDDR. Follow mode bypasses both, as it
+//! talks to the flash directly, where only the flash's own block protection applies.
+
+/// Flash memory size select
+pub const FMSSR: usize = 0x1007;
+/// Flash control register 1, with the protect region lock
+pub const FLHCTRL1R: usize = 0x1031;
+/// FLHCTRL1R bit locking the protect region registers until reset, write once
+pub const FLHCTRL1R_PLOCK: u8 = 1 << 7;
+
+/// Protect regions, as base address registers 0 and 1 (in 4 KiB units) and size register
+pub const PROTECT_REGIONS: [(usize, usize, usize); 2] = [
+    (0x1070, 0x1071, 0x1072),
+    (0x1073, 0x1074, 0x1075),
+];
+/// PxZR bits 3:0, region size as 4 KiB shifted left
+pub const PZR_SIZE: u8 = 0b1111;
+/// PxZR bits protecting a region from host writes, host reads, and EC writes
+pub const PZR_HOST_WRITE: u8 = 1 << 4;
+pub const PZR_HOST_READ: u8 = 1 << 5;
+pub const PZR_EC_WRITE: u8 = 1 << 6;
+
+/// Flash size decoded by SMFI, 64 KiB shifted left by FMSSR bits 3:0, up to the flash size
+pub fn flash_size(xram: &[u8], flash_len: usize) -> usize {
+    let shift = (xram[FMSSR] & 0b1111).min(8);
+    (64 * 1024 << shift).min(flash_len)
+}
+
+/// Check if an access to a flash address is blocked by a protect region, with `kind` being
+/// one of the PZR protection bits
+pub fn protected(xram: &[u8], address: usize, kind: u8) -> bool {
+    PROTECT_REGIONS.iter().any(|&(ba0, ba1, zr)| {
+        let size_reg = xram[zr];
+        if size_reg & kind == 0 {
+            return false;
+        }
+        let base = ((xram[ba1] as usize & 0xF) << 8 | xram[ba0] as usize) << 12;
+        let size = 4096 << (size_reg & PZR_SIZE);
+        address >= base && address < base + size
+    })
+}
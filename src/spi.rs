@@ -1,7 +1,5 @@
 // SPDX-License-Identifier: MIT
 
-#![allow(clippy::needless_range_loop)]
-
 use std::collections::VecDeque;
 
 #[cfg(feature = "debug_spi")]
@@ -14,176 +12,689 @@ macro_rules! debug {
     ($($arg:tt)*) => (());
 }
 
+/// Protocol violations kept for the spi command
+const ERRORS_MAX: usize = 1024;
+
+/// Status register 1: write in progress
+pub const STATUS_WIP: u8 = 1 << 0;
+/// Status register 1: write enable latch
+pub const STATUS_WEL: u8 = 1 << 1;
+/// Status register 1: status register protect (BPL on SST parts), effective while WP# is asserted
+pub const STATUS_SRP0: u8 = 1 << 7;
+
+/// Model of an SPI flash part
+pub struct FlashDevice {
+    pub name: &'static str,
+    /// Returned by 0x9F
+    pub jedec_id: [u8; 3],
+    /// Returned after the manufacturer ID by 0x90, and by 0xAB
+    pub device_id: u8,
+    pub size: usize,
+    /// Number of block protect bits in status register 1 decoded, starting at bit 2. A value N
+    /// above 0 protects 64 KiB << (N - 1) at the top, or at the bottom with the top/bottom bit
+    pub bp_bits: u8,
+    /// Top/bottom protect bit in status register 1
+    pub tb_bit: Option<u8>,
+    /// Writable bits of status registers 1 to 3
+    pub status_mask: [u8; 3],
+    /// Status registers 1 to 3 at power up
+    pub status_reset: [u8; 3],
+    /// Supports AAI word program (0xAD)
+    pub aai: bool,
+    /// Size erased by 0xD7
+    pub d7_erase: Option<usize>,
+    /// Supports SFDP, and dual and quad reads and ID reads
+    pub quad: bool,
+}
+
+impl FlashDevice {
+    /// SFDP header and JEDEC basic flash parameter table (revision 1.0)
+    fn sfdp(&self) -> Vec<u8> {
+        let mut data = vec![0xFF; 0x30];
+        data[0x00..0x08].copy_from_slice(&[b'S', b'F', b'D', b'P', 0x00, 0x01, 0x00, 0xFF]);
+        data[0x08..0x10].copy_from_slice(&[0x00, 0x00, 0x01, 9, 0x30, 0x00, 0x00, 0xFF]);
+        let dwords: [u32; 9] = [
+            // 4 KiB erase with 0x20, 1-1-2, 1-2-2, 1-4-4, and 1-1-4 reads, 3 byte addresses
+            0xFFF1_2005,
+            // Density in bits, minus one
+            (self.size as u32) * 8 - 1,
+            // 0xEB with 4 dummy and 2 mode clocks, 0x6B with 8 dummy clocks
+            0x6B08_EB44,
+            // 0x3B with 8 dummy clocks, 0xBB with 4 mode clocks
+            0xBB80_3B08,
+            // No 2-2-2 or 4-4-4 reads
+            0xFFFF_FFEE,
+            0xFF00_FFFF,
+            0xFF00_FFFF,
+            // 4 KiB with 0x20, 32 KiB with 0x52, 64 KiB with 0xD8
+            0x520F_200C,
+            0x00FF_D810,
+        ];
+        for dword in dwords.iter() {
+            data.extend_from_slice(&dword.to_le_bytes());
+        }
+        data
+    }
+}
+
+/// Internal flash of ITE ECs
+pub static ITE: FlashDevice = FlashDevice {
+    name: "ite",
+    // Not documented, kept from earlier versions of the model
+    jedec_id: [0xEF, 0xEF, 0xEF],
+    device_id: 0xEF,
+    size: 128 * 1024,
+    bp_bits: 0,
+    tb_bit: None,
+    status_mask: [0, 0, 0],
+    status_reset: [0, 0, 0],
+    aai: true,
+    d7_erase: Some(1024),
+    quad: false,
+};
+
+pub static W25Q16JV: FlashDevice = FlashDevice {
+    name: "w25q16jv",
+    jedec_id: [0xEF, 0x40, 0x15],
+    device_id: 0x14,
+    size: 2 * 1024 * 1024,
+    bp_bits: 3,
+    tb_bit: Some(5),
+    status_mask: [0xFC, 0x43, 0x64],
+    status_reset: [0, 0, 0],
+    aai: false,
+    d7_erase: None,
+    quad: true,
+};
+
+pub static GD25Q16C: FlashDevice = FlashDevice {
+    name: "gd25q16c",
+    jedec_id: [0xC8, 0x40, 0x15],
+    device_id: 0x14,
+    size: 2 * 1024 * 1024,
+    bp_bits: 3,
+    tb_bit: Some(5),
+    status_mask: [0xFC, 0x43, 0x60],
+    status_reset: [0, 0, 0],
+    aai: false,
+    d7_erase: None,
+    quad: true,
+};
+
+pub static SST25VF016B: FlashDevice = FlashDevice {
+    name: "sst25vf016b",
+    jedec_id: [0xBF, 0x25, 0x41],
+    device_id: 0x41,
+    size: 2 * 1024 * 1024,
+    // BP3 is writable but not decoded, and BP2:0 protect the same upper ranges as the Winbond
+    // parts. BPL is handled like SRP0
+    bp_bits: 3,
+    tb_bit: None,
+    status_mask: [0xBC, 0, 0],
+    // BP2:0 are set at power up, protecting the whole chip until firmware clears them
+    status_reset: [0x1C, 0, 0],
+    aai: true,
+    d7_erase: None,
+    quad: false,
+};
+
+pub static DEVICES: [&FlashDevice; 4] = [&ITE, &W25Q16JV, &GD25Q16C, &SST25VF016B];
+
+/// Find a flash device by name
+pub fn device(name: &str) -> Option<&'static FlashDevice> {
+    DEVICES.iter().copied().find(|device| device.name == name)
+}
+
+/// Data returned while the chip stays selected
+enum Stream {
+    /// Flash contents, from an address
+    Read(usize),
+    /// Status register, by number
+    Status(usize),
+    /// Fixed data, from a position, repeating if true
+    Bytes(Vec<u8>, usize, bool),
+}
+
 pub struct Spi {
-    pub write: bool,
-    pub fast_read_addr: Option<usize>,
+    pub device: &'static FlashDevice,
+    /// Status registers 1 to 3. WIP is computed from the busy time
+    pub status: [u8; 3],
+    /// WP# pin asserted
+    pub wp: bool,
+    /// Current step, set by the caller before selecting the chip
+    pub now: u64,
+    /// Steps reporting busy after a program or status write
+    pub program_time: u64,
+    /// Steps reporting busy after an erase
+    pub erase_time: u64,
+    /// Step at which the current program or erase completes
+    busy_until: u64,
+    /// Status write enabled by 0x50, without setting WEL
+    volatile_write: bool,
     pub aai_addr: Option<usize>,
+    stream: Option<Stream>,
     pub input: VecDeque<u8>,
     pub output: VecDeque<u8>,
+    /// Protocol violations, shown by the spi command
+    pub errors: VecDeque<String>,
+    /// Ranges changed by programs and erases, as offset, length, and command, for the caller to
+    /// track flash changes
+    pub written: Vec<(usize, usize, &'static str)>,
 }
 
-#[allow(clippy::new_without_default)]
 impl Spi {
-    pub fn new() -> Self {
+    pub fn new(device: &'static FlashDevice) -> Self {
         Self {
-            write: false,
-            fast_read_addr: None,
+            device,
+            status: device.status_reset,
+            wp: false,
+            now: 0,
+            program_time: 0,
+            erase_time: 0,
+            busy_until: 0,
+            volatile_write: false,
             aai_addr: None,
+            stream: None,
             input: VecDeque::new(),
             output: VecDeque::new(),
+            errors: VecDeque::new(),
+            written: Vec::new(),
         }
     }
 
-    pub fn step(&mut self, flash: &mut [u8], _flash_name: &str) {
-        if let Some(command) = self.input.pop_front() {
-            debug!("\n[spi {}", _flash_name);
+    /// Record a protocol violation by firmware or the host. The transaction is ignored, like real
+    /// chips do
+    pub fn error(&mut self, message: String) {
+        debug!(" (error: {})", message);
+        if self.errors.len() >= ERRORS_MAX {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(message);
+    }
+
+    fn addr(&mut self) -> Result<usize, String> {
+        if self.input.len() < 3 {
+            return Err("address missing".to_string());
+        }
+        let a2 = self.input.pop_front().unwrap();
+        let a1 = self.input.pop_front().unwrap();
+        let a0 = self.input.pop_front().unwrap();
+
+        let addr =
+            (a0 as usize) |
+            (a1 as usize) << 8 |
+            (a2 as usize) << 16;
+
+        debug!(" 0x{:06X}", addr);
+
+        Ok(addr)
+    }
+
+    /// Skip dummy and mode bytes
+    fn dummy(&mut self, count: usize) {
+        for _ in 0..count {
+            self.input.pop_front();
+        }
+    }
+
+    /// Range protected by the block protect bits
+    fn protected(&self, addr: usize) -> bool {
+        let bp = (self.status[0] >> 2) & ((1 << self.device.bp_bits) - 1);
+        if bp == 0 {
+            return false;
+        }
 
-            self.fast_read_addr = None;
+        let size = (64 * 1024usize << (bp - 1)).min(self.device.size);
+        let addr = addr % self.device.size;
+        let bottom = matches!(self.device.tb_bit, Some(bit) if self.status[0] & (1 << bit) != 0);
+        if bottom {
+            addr < size
+        } else {
+            addr >= self.device.size - size
+        }
+    }
 
-            match command {
-                0x01 => {
-                    debug!(" write status");
+    /// Check if a program or erase is still in progress
+    fn busy(&self) -> bool {
+        self.now < self.busy_until
+    }
 
-                    let _value = self.input.pop_front().expect("spi wrate status value missing");
+    /// Check the write enable latch and block protection before a program or erase
+    fn write_enabled(&mut self, addr: usize) -> bool {
+        if self.status[0] & STATUS_WEL == 0 {
+            debug!(" (write not enabled)");
+            return false;
+        }
+        if self.protected(addr) {
+            debug!(" (protected)");
+            self.status[0] &= !STATUS_WEL;
+            return false;
+        }
+        true
+    }
 
-                    debug!(" 0x{:02X}", _value);
-                },
-                0x02 => {
-                    debug!(" page program");
+    fn erase(&mut self, flash: &mut [u8], addr: usize, size: usize, name: &'static str) {
+        let start = addr & !(size - 1);
+        if self.write_enabled(start) {
+            for i in start..start + size {
+                flash[i % flash.len()] = 0xFF;
+            }
+            self.written.push((start % flash.len(), size.min(flash.len()), name));
+            self.status[0] &= !STATUS_WEL;
+            self.busy_until = self.now + self.erase_time;
+        }
+    }
 
-                    let a2 = self.input.pop_front().expect("spi page program value missing");
-                    let a1 = self.input.pop_front().expect("spi page program value missing");
-                    let a0 = self.input.pop_front().expect("spi page program value missing");
+    fn command(&mut self, command: u8, flash: &mut [u8]) -> Result<(), String> {
+        match command {
+            0x01 | 0x31 | 0x11 => {
+                let first = match command {
+                    0x01 => 0,
+                    0x31 => 1,
+                    _ => 2,
+                };
+                debug!(" write status {}", first + 1);
+
+                let enabled = self.status[0] & STATUS_WEL != 0 || self.volatile_write;
+                let locked = self.wp && self.status[0] & STATUS_SRP0 != 0;
+                if ! enabled {
+                    debug!(" (write not enabled)");
+                } else if locked {
+                    debug!(" (locked)");
+                }
 
-                    let mut address = {
-                        (a0 as usize) |
-                        (a1 as usize) << 8 |
-                        (a2 as usize) << 16
-                    };
+                let mut reg = first;
+                while let Some(value) = self.input.pop_front() {
+                    debug!(" 0x{:02X}", value);
+                    if enabled && ! locked && reg < 3 {
+                        let mask = self.device.status_mask[reg];
+                        self.status[reg] = (self.status[reg] & !mask) | (value & mask);
+                    }
+                    reg += 1;
+                }
 
-                    debug!(" 0x{:06X}", address);
+                self.status[0] &= !STATUS_WEL;
+                self.volatile_write = false;
+                if enabled && ! locked {
+                    self.busy_until = self.now + self.program_time;
+                }
+            },
+            0x02 => {
+                debug!(" page program");
 
+                let mut addr = self.addr()?;
+                if self.write_enabled(addr) {
                     while let Some(value) = self.input.pop_front() {
-                        debug!(" [0x{:06X} = 0x{:02X}]", address, value);
+                        debug!(" [0x{:06X} = 0x{:02X}]", addr, value);
 
-                        flash[address] = value;
+                        // Programming can only clear bits
+                        flash[addr % flash.len()] &= value;
+                        self.written.push((addr % flash.len(), 1, "page program"));
 
-                        if address & 0xFF == 0xFF {
-                            address -= 0xFF;
+                        if addr & 0xFF == 0xFF {
+                            addr -= 0xFF;
                         } else {
-                            address += 1;
+                            addr += 1;
                         }
                     }
-                },
-                0x04 => {
-                    debug!(" write disable");
-                    self.write = false;
-                    self.aai_addr = None;
-                },
-                0x05 => {
-                    debug!(" read status");
-                    let value = (self.write as u8) << 1;
-                    self.output.push_back(value);
-                },
-                0x06 => {
-                    debug!(" write enable");
-                    self.write = true;
-                },
-                0x0B => {
-                    debug!(" fast read");
-
-                    let a2 = self.input.pop_front().expect("spi fast read value missing");
-                    let a1 = self.input.pop_front().expect("spi fast read value missing");
-                    let a0 = self.input.pop_front().expect("spi fast read value missing");
-                    let _d = self.input.pop_front().expect("spi fast read value missing");
-
-                    let address = {
-                        (a0 as usize) |
-                        (a1 as usize) << 8 |
-                        (a2 as usize) << 16
-                    };
-
-                    debug!(" 0x{:06X}", address);
-
-                    self.fast_read_addr = Some(address);
-                },
-                0x50 => {
-                    debug!(" write volatile status register");
-                    // TODO
-                },
-                0x60 => {
-                    debug!(" chip erase");
-                    for i in 0..flash.len() {
-                        flash[i] = 0xFF;
+                    self.status[0] &= !STATUS_WEL;
+                    self.busy_until = self.now + self.program_time;
+                } else {
+                    self.input.clear();
+                }
+            },
+            0x03 => {
+                debug!(" read");
+                let addr = self.addr()?;
+                self.stream = Some(Stream::Read(addr));
+            },
+            0x04 => {
+                debug!(" write disable");
+                self.status[0] &= !STATUS_WEL;
+                self.aai_addr = None;
+            },
+            0x05 => {
+                debug!(" read status 1");
+                self.stream = Some(Stream::Status(0));
+            },
+            0x06 => {
+                debug!(" write enable");
+                self.status[0] |= STATUS_WEL;
+            },
+            0x0B => {
+                debug!(" fast read");
+                let addr = self.addr()?;
+                self.dummy(1);
+                self.stream = Some(Stream::Read(addr));
+            },
+            0x15 | 0x35 if self.device.quad => {
+                let reg = if command == 0x35 { 1 } else { 2 };
+                debug!(" read status {}", reg + 1);
+                self.stream = Some(Stream::Status(reg));
+            },
+            0x20 => {
+                debug!(" 4 KiB erase");
+                let addr = self.addr()?;
+                self.erase(flash, addr, 4 * 1024, "4 KiB erase");
+            },
+            0x3B | 0x6B if self.device.quad => {
+                debug!(" {} output read", if command == 0x3B { "dual" } else { "quad" });
+                let addr = self.addr()?;
+                self.dummy(1);
+                self.stream = Some(Stream::Read(addr));
+            },
+            0xBB | 0xEB if self.device.quad => {
+                debug!(" {} I/O read", if command == 0xBB { "dual" } else { "quad" });
+                let addr = self.addr()?;
+                self.dummy(if command == 0xBB { 1 } else { 3 });
+                self.stream = Some(Stream::Read(addr));
+            },
+            0x50 => {
+                debug!(" write volatile status register");
+                self.volatile_write = true;
+            },
+            0x52 => {
+                debug!(" 32 KiB erase");
+                let addr = self.addr()?;
+                self.erase(flash, addr, 32 * 1024, "32 KiB erase");
+            },
+            0x5A if self.device.quad => {
+                debug!(" read sfdp");
+                let addr = self.addr()?;
+                self.dummy(1);
+                self.stream = Some(Stream::Bytes(self.device.sfdp(), addr, false));
+            },
+            0x60 | 0xC7 => {
+                debug!(" chip erase");
+                if self.status[0] & STATUS_WEL == 0 {
+                    debug!(" (write not enabled)");
+                } else if (0..self.device.size).step_by(64 * 1024).any(|addr| self.protected(addr)) {
+                    debug!(" (protected)");
+                    self.status[0] &= !STATUS_WEL;
+                } else {
+                    for value in flash.iter_mut() {
+                        *value = 0xFF;
                     }
-                },
-                0x9F => {
-                    debug!(" jedec id");
-                    self.output.push_back(0xEF);
-                    self.output.push_back(0xEF);
-                    self.output.push_back(0xEF);
-                },
-                0xAD => {
-                    debug!(" aai program");
-
-                    let addr = if self.input.len() > 2 {
-                        let a2 = self.input.pop_front().expect("spi aai program value missing");
-                        let a1 = self.input.pop_front().expect("spi aai program value missing");
-                        let a0 = self.input.pop_front().expect("spi aai program value missing");
-
-                        (a0 as usize) |
-                        (a1 as usize) << 8 |
-                        (a2 as usize) << 16
-                    } else {
-                        self.aai_addr.expect("spi aai address not set")
-                    };
-
-                    debug!(" 0x{:06X}", addr);
-
-                    let d0 = self.input.pop_front().expect("spi aai program value missing");
-                    let d1 = self.input.pop_front().expect("spi aai program value missing");
-
-                    debug!(" = 0x{:02X}, 0x{:02X}", d0, d1);
-
-                    flash[addr] = d0;
-                    flash[addr + 1] = d1;
-
+                    self.written.push((0, flash.len(), "chip erase"));
+                    self.status[0] &= !STATUS_WEL;
+                    self.busy_until = self.now + self.erase_time;
+                }
+            },
+            0x90 => {
+                debug!(" manufacturer/device id");
+                self.addr()?;
+                let id = vec![self.device.jedec_id[0], self.device.device_id];
+                self.stream = Some(Stream::Bytes(id, 0, true));
+            },
+            0x92 | 0x94 if self.device.quad => {
+                debug!(" {} I/O manufacturer/device id", if command == 0x92 { "dual" } else { "quad" });
+                self.addr()?;
+                self.dummy(if command == 0x92 { 1 } else { 3 });
+                let id = vec![self.device.jedec_id[0], self.device.device_id];
+                self.stream = Some(Stream::Bytes(id, 0, true));
+            },
+            0x9F => {
+                debug!(" jedec id");
+                self.stream = Some(Stream::Bytes(self.device.jedec_id.to_vec(), 0, true));
+            },
+            0xAB => {
+                debug!(" release power down/device id");
+                self.dummy(3);
+                self.stream = Some(Stream::Bytes(vec![self.device.device_id], 0, true));
+            },
+            0xAD if self.device.aai => {
+                debug!(" aai program");
+
+                let addr = if self.input.len() > 2 {
+                    self.addr()?
+                } else {
+                    self.aai_addr.ok_or("aai address not set")?
+                };
+
+                let (d0, d1) = match (self.input.pop_front(), self.input.pop_front()) {
+                    (Some(d0), Some(d1)) => (d0, d1),
+                    _ => return Err("aai program data missing".to_string()),
+                };
+
+                debug!(" = 0x{:02X}, 0x{:02X}", d0, d1);
+
+                // The write enable latch stays set until write disable
+                if self.write_enabled(addr) {
+                    flash[addr % flash.len()] &= d0;
+                    flash[(addr + 1) % flash.len()] &= d1;
+                    self.written.push((addr % flash.len(), 1, "aai program"));
+                    self.written.push(((addr + 1) % flash.len(), 1, "aai program"));
                     self.aai_addr = Some(addr + 2);
-                },
-                0xD7 => {
-                    debug!(" page erase");
+                    self.busy_until = self.now + self.program_time;
+                } else {
+                    self.aai_addr = None;
+                }
+            },
+            0xD7 if self.device.d7_erase.is_some() => {
+                debug!(" page erase");
+                let addr = self.addr()?;
+                self.erase(flash, addr, self.device.d7_erase.unwrap(), "page erase");
+            },
+            0xD8 => {
+                debug!(" 64 KiB erase");
+                let addr = self.addr()?;
+                self.erase(flash, addr, 64 * 1024, "64 KiB erase");
+            },
+            _ => {
+                return Err(format!("unknown command for {}", self.device.name));
+            }
+        }
+        Ok(())
+    }
 
-                    let a2 = self.input.pop_front().expect("spi page erase value missing");
-                    let a1 = self.input.pop_front().expect("spi page erase value missing");
-                    let a0 = self.input.pop_front().expect("spi page erase value missing");
+    /// Next byte returned while the chip stays selected
+    fn next(&mut self, flash: &[u8]) -> Option<u8> {
+        match self.stream.as_mut()? {
+            Stream::Read(addr) => {
+                let value = flash[*addr % flash.len()];
+                *addr += 1;
+                Some(value)
+            },
+            Stream::Status(0) => Some(self.status[0] | self.busy() as u8 * STATUS_WIP),
+            Stream::Status(reg) => Some(self.status[*reg]),
+            Stream::Bytes(data, pos, repeat) => {
+                if *repeat {
+                    *pos %= data.len();
+                }
+                let value = data.get(*pos).copied().unwrap_or(0xFF);
+                *pos += 1;
+                Some(value)
+            },
+        }
+    }
 
-                    let addr =
-                        (a0 as usize) |
-                        (a1 as usize) << 8 |
-                        (a2 as usize) << 16;
+    /// Run the command sent since the chip was selected, if any, and queue the next byte to be
+    /// read
+    pub fn step(&mut self, flash: &mut [u8], _flash_name: &str) {
+        if let Some(command) = self.input.pop_front() {
+            debug!("\n[spi {}", _flash_name);
 
-                    debug!(" 0x{:06X}", addr);
+            self.stream = None;
 
-                    for i in addr..addr + 256 {
-                        flash[i] = 0xFF;
-                    }
-                },
-                _ => {
-                    panic!("unknown SPI command 0x{:02X}", command);
-                }
+            let busy_allowed = matches!(command, 0x05 | 0x15 | 0x35);
+            if self.busy() && ! busy_allowed {
+                self.error(format!("command 0x{:02X} ignored while busy", command));
+            } else if let Err(err) = self.command(command, flash) {
+                self.error(format!("command 0x{:02X} ignored: {}", command, err));
+            } else if ! self.input.is_empty() {
+                self.error(format!("command 0x{:02X}: {} extra bytes ignored", command, self.input.len()));
             }
-
-            assert_eq!(self.input.len(), 0);
+            self.input.clear();
 
             debug!("]");
         }
 
-        if let Some(mut address) = self.fast_read_addr.take() {
-            self.output.push_back(flash[address]);
-            address += 1;
-            if address < flash.len() {
-                self.fast_read_addr = Some(address);
+        if let Some(value) = self.next(flash) {
+            self.output.push_back(value);
+        }
+    }
+
+    /// Read a byte while the chip is selected, returning 0xFF if there is nothing to read
+    pub fn read(&mut self, flash: &mut [u8], flash_name: &str) -> u8 {
+        self.step(flash, flash_name);
+        match self.output.pop_front() {
+            Some(value) => value,
+            None => {
+                self.error("read without a pending response".to_string());
+                0xFF
             }
         }
     }
+
+    /// Deselect the chip, running a command that was only sent
+    pub fn deselect(&mut self, flash: &mut [u8], flash_name: &str) {
+        if ! self.input.is_empty() {
+            self.step(flash, flash_name);
+        }
+        self.stream = None;
+        self.output.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn erased(device: &FlashDevice) -> Vec<u8> {
+        vec![0xFF; device.size]
+    }
+
+    /// Select the chip, send bytes, and deselect it
+    fn send(spi: &mut Spi, flash: &mut [u8], bytes: &[u8]) {
+        spi.input.extend(bytes.iter());
+        spi.deselect(flash, "test");
+    }
+
+    fn read_status(spi: &mut Spi, flash: &mut [u8]) -> u8 {
+        spi.input.push_back(0x05);
+        let value = spi.read(flash, "test");
+        spi.deselect(flash, "test");
+        value
+    }
+
+    #[test]
+    fn program_requires_write_enable() {
+        let mut spi = Spi::new(&W25Q16JV);
+        let mut flash = erased(&W25Q16JV);
+
+        send(&mut spi, &mut flash, &[0x02, 0x00, 0x01, 0x00, 0x12]);
+        assert_eq!(flash[0x100], 0xFF);
+
+        send(&mut spi, &mut flash, &[0x06]);
+        assert_ne!(read_status(&mut spi, &mut flash) & STATUS_WEL, 0);
+        send(&mut spi, &mut flash, &[0x02, 0x00, 0x01, 0x00, 0x12]);
+        assert_eq!(flash[0x100], 0x12);
+        assert_eq!(read_status(&mut spi, &mut flash) & STATUS_WEL, 0);
+    }
+
+    #[test]
+    fn block_protect_blocks_top() {
+        let mut spi = Spi::new(&W25Q16JV);
+        let mut flash = erased(&W25Q16JV);
+
+        // BP = 1 protects the upper 64 KiB
+        send(&mut spi, &mut flash, &[0x06]);
+        send(&mut spi, &mut flash, &[0x01, 0b0000_0100]);
+
+        send(&mut spi, &mut flash, &[0x06]);
+        send(&mut spi, &mut flash, &[0x02, 0x1F, 0x00, 0x00, 0x12]);
+        assert_eq!(flash[0x1F_0000], 0xFF);
+        // A blocked program clears WEL
+        assert_eq!(read_status(&mut spi, &mut flash) & STATUS_WEL, 0);
+
+        send(&mut spi, &mut flash, &[0x06]);
+        send(&mut spi, &mut flash, &[0x02, 0x1E, 0xFF, 0x00, 0x12]);
+        assert_eq!(flash[0x1E_FF00], 0x12);
+    }
+
+    #[test]
+    fn sst_ignores_bp3() {
+        let mut spi = Spi::new(&SST25VF016B);
+        let mut flash = erased(&SST25VF016B);
+
+        send(&mut spi, &mut flash, &[0x06]);
+        send(&mut spi, &mut flash, &[0x01, 0b0010_0000]);
+        send(&mut spi, &mut flash, &[0x06]);
+        send(&mut spi, &mut flash, &[0x02, 0x1F, 0x00, 0x00, 0x12]);
+        assert_eq!(flash[0x1F_0000], 0x12);
+    }
+
+    #[test]
+    fn busy_until_deadline() {
+        let mut spi = Spi::new(&W25Q16JV);
+        let mut flash = erased(&W25Q16JV);
+        spi.program_time = 100;
+        spi.now = 1000;
+
+        send(&mut spi, &mut flash, &[0x06]);
+        send(&mut spi, &mut flash, &[0x02, 0x00, 0x00, 0x00, 0x12]);
+
+        // Other commands are ignored while busy
+        send(&mut spi, &mut flash, &[0x06]);
+        assert_eq!(spi.errors.len(), 1);
+
+        // Polling does not shorten the program time
+        for _ in 0..200 {
+            assert_ne!(read_status(&mut spi, &mut flash) & STATUS_WIP, 0);
+        }
+        spi.now = 1099;
+        assert_ne!(read_status(&mut spi, &mut flash) & STATUS_WIP, 0);
+        spi.now = 1100;
+        assert_eq!(read_status(&mut spi, &mut flash), 0);
+    }
+
+    #[test]
+    fn busy_clears_after_fixed_delay() {
+        let mut spi = Spi::new(&W25Q16JV);
+        let mut flash = erased(&W25Q16JV);
+        spi.erase_time = 50;
+
+        send(&mut spi, &mut flash, &[0x06]);
+        send(&mut spi, &mut flash, &[0x20, 0x00, 0x00, 0x00]);
+
+        // Firmware waits without reading status, then programs
+        spi.now = 50;
+        send(&mut spi, &mut flash, &[0x06]);
+        send(&mut spi, &mut flash, &[0x02, 0x00, 0x00, 0x00, 0x12]);
+        assert!(spi.errors.is_empty());
+        assert_eq!(flash[0], 0x12);
+    }
+
+    #[test]
+    fn sst_powers_up_protected() {
+        let mut spi = Spi::new(&SST25VF016B);
+        let mut flash = erased(&SST25VF016B);
+        assert_eq!(read_status(&mut spi, &mut flash), 0x1C);
+
+        send(&mut spi, &mut flash, &[0x06]);
+        send(&mut spi, &mut flash, &[0x02, 0x00, 0x00, 0x00, 0x12]);
+        assert_eq!(flash[0], 0xFF);
+
+        // Clearing BP2:0 with EWSR allows programming
+        send(&mut spi, &mut flash, &[0x50]);
+        send(&mut spi, &mut flash, &[0x01, 0x00]);
+        send(&mut spi, &mut flash, &[0x06]);
+        send(&mut spi, &mut flash, &[0x02, 0x00, 0x00, 0x00, 0x12]);
+        assert_eq!(flash[0], 0x12);
+    }
+
+    #[test]
+    fn quad_id_reads_need_quad_device() {
+        let mut spi = Spi::new(&ITE);
+        let mut flash = erased(&ITE);
+        send(&mut spi, &mut flash, &[0x94, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(spi.errors.len(), 1);
+
+        let mut spi = Spi::new(&W25Q16JV);
+        let mut flash = erased(&W25Q16JV);
+        spi.input.extend([0x94, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00].iter());
+        assert_eq!(spi.read(&mut flash, "test"), 0xEF);
+        assert_eq!(spi.read(&mut flash, "test"), 0x14);
+        assert!(spi.errors.is_empty());
+    }
 }
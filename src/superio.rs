@@ -0,0 +1,236 @@
+// SPDX-License-Identifier: MIT
+
+//! ITE SuperIO configuration space, accessed by the host through an index port and a data port.
+//!
+//! Registers below 0x30 are global, and the rest belong to the logical device selected with
+//! register 0x07. The host has to write the key sequence 0x87, 0x01, 0x55, 0x55 to the index port
+//! before configuration is accessible, and locks it again by writing bit 1 of register 0x02. The
+//! chip ID and version registers 0x20 to 0x22 are readable without it, so the host can probe for
+//! the EC before entering configuration mode.
+//!
+//! Firmware reaches the same registers through the EC2I bridge, with IHIOA selecting the index
+//! (0) or data (1) port, without the key sequence. This is the only copy of the configuration, so
+//! the host and firmware see each other's changes, and host cycles are decoded with it.
+
+pub const LDN_UART1: u8 = 0x01;
+pub const LDN_UART2: u8 = 0x02;
+pub const LDN_SWUC: u8 = 0x04;
+pub const LDN_MOUSE: u8 = 0x05;
+pub const LDN_KEYBOARD: u8 = 0x06;
+pub const LDN_SMFI: u8 = 0x0F;
+pub const LDN_BRAM: u8 = 0x10;
+pub const LDN_PMC1: u8 = 0x11;
+pub const LDN_PMC2: u8 = 0x12;
+pub const LDN_SSPI: u8 = 0x13;
+pub const LDN_PECI: u8 = 0x14;
+pub const LDN_PMC3: u8 = 0x17;
+pub const LDN_PMC4: u8 = 0x18;
+pub const LDN_PMC5: u8 = 0x19;
+
+/// Logical devices of each PMC channel
+pub const LDN_PMC: [u8; 5] = [LDN_PMC1, LDN_PMC2, LDN_PMC3, LDN_PMC4, LDN_PMC5];
+
+const LDNS: [u8; 14] = [
+    LDN_UART1, LDN_UART2, LDN_SWUC, LDN_MOUSE, LDN_KEYBOARD, LDN_SMFI, LDN_BRAM,
+    LDN_PMC1, LDN_PMC2, LDN_SSPI, LDN_PECI, LDN_PMC3, LDN_PMC4, LDN_PMC5,
+];
+
+const KEY: [u8; 4] = [0x87, 0x01, 0x55, 0x55];
+
+const ACTIVATE: u8 = 0x30;
+const BASE0_HIGH: u8 = 0x60;
+const BASE0_LOW: u8 = 0x61;
+const BASE1_HIGH: u8 = 0x62;
+const BASE1_LOW: u8 = 0x63;
+const IRQ_NUMBER: u8 = 0x70;
+const IRQ_TYPE: u8 = 0x71;
+
+pub struct SuperIo {
+    /// Register selected with the index port
+    pub addr: u8,
+    /// Configuration registers are accessible
+    pub unlocked: bool,
+    /// Bytes of the key sequence received
+    key: usize,
+    /// Selected logical device
+    ldn: u8,
+    /// Registers of each logical device, indexed by logical device number
+    devices: Box<[[u8; 256]]>,
+}
+
+#[allow(clippy::new_without_default)]
+impl SuperIo {
+    pub fn new() -> Self {
+        let mut superio = Self {
+            addr: 0,
+            unlocked: false,
+            key: 0,
+            ldn: 0,
+            devices: vec![[0; 256]; 0x20].into_boxed_slice(),
+        };
+
+        // Power on defaults of the devices the host expects to find
+        superio.set_ports(LDN_KEYBOARD, Some((0x60, 0x64)));
+        superio.devices[LDN_KEYBOARD as usize][IRQ_NUMBER as usize] = 1;
+        superio.devices[LDN_MOUSE as usize][ACTIVATE as usize] = 1;
+        superio.devices[LDN_MOUSE as usize][IRQ_NUMBER as usize] = 12;
+        superio.devices[LDN_SMFI as usize][ACTIVATE as usize] = 1;
+        superio.set_ports(LDN_PMC1, Some((0x62, 0x66)));
+        superio.set_ports(LDN_PMC2, Some((0x68, 0x6C)));
+        superio.set_ports(LDN_PMC3, Some((0x6A, 0x6E)));
+
+        superio
+    }
+
+    fn register(&self, ldn: u8, reg: u8) -> u8 {
+        self.devices[ldn as usize][reg as usize]
+    }
+
+    fn base(&self, ldn: u8, high: u8, low: u8) -> u16 {
+        u16::from_be_bytes([self.register(ldn, high), self.register(ldn, low)])
+    }
+
+    /// Check if a logical device is activated
+    pub fn active(&self, ldn: u8) -> bool {
+        self.register(ldn, ACTIVATE) & 1 != 0
+    }
+
+    /// Get the I/O bases of a logical device, if activated
+    pub fn ports(&self, ldn: u8) -> Option<(u16, u16)> {
+        if self.active(ldn) {
+            Some((
+                self.base(ldn, BASE0_HIGH, BASE0_LOW),
+                self.base(ldn, BASE1_HIGH, BASE1_LOW),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Set the I/O bases of a logical device, activating it, or deactivate it
+    pub fn set_ports(&mut self, ldn: u8, ports_opt: Option<(u16, u16)>) {
+        let device = &mut self.devices[ldn as usize];
+        match ports_opt {
+            Some((base0, base1)) => {
+                device[ACTIVATE as usize] = 1;
+                device[BASE0_HIGH as usize] = (base0 >> 8) as u8;
+                device[BASE0_LOW as usize] = base0 as u8;
+                device[BASE1_HIGH as usize] = (base1 >> 8) as u8;
+                device[BASE1_LOW as usize] = base1 as u8;
+            },
+            None => {
+                device[ACTIVATE as usize] = 0;
+            }
+        }
+    }
+
+    /// Get the host memory base of H2RAM, from SMFI registers 0xF5 to 0xF7 as address bits 15:12,
+    /// 23:16, and 31:24
+    pub fn h2ram_base(&self) -> u32 {
+        u32::from_be_bytes([
+            self.register(LDN_SMFI, 0xF7),
+            self.register(LDN_SMFI, 0xF6),
+            self.register(LDN_SMFI, 0xF5) & 0xF0,
+            0,
+        ])
+    }
+
+    /// Get the IRQ number of a logical device, if activated and assigned
+    pub fn irq(&self, ldn: u8) -> Option<u8> {
+        match self.register(ldn, IRQ_NUMBER) & 0xF {
+            0 => None,
+            _ if self.register(ldn, ACTIVATE) & 1 == 0 => None,
+            irq => Some(irq),
+        }
+    }
+
+    /// Host write to the index port, which also receives the key sequence
+    pub fn write_addr(&mut self, value: u8) {
+        self.addr = value;
+        if self.unlocked {
+            return;
+        }
+        if value == KEY[self.key] {
+            self.key += 1;
+            if self.key == KEY.len() {
+                self.key = 0;
+                self.unlocked = true;
+            }
+        } else {
+            self.key = if value == KEY[0] { 1 } else { 0 };
+        }
+    }
+
+    fn device_register(&self) -> bool {
+        LDNS.contains(&self.ldn) && match self.addr {
+            ACTIVATE | BASE0_HIGH ..= BASE1_LOW | IRQ_NUMBER | IRQ_TYPE | 0xF0 ..= 0xFF => true,
+            _ => false,
+        }
+    }
+
+    /// Host read of the data port, if the selected register exists and is either a chip ID
+    /// register or unlocked
+    pub fn read(&self, id: u16, version: u8) -> Option<u8> {
+        if ! self.unlocked && ! matches!(self.addr, 0x20 ..= 0x22) {
+            return None;
+        }
+        self.read_register(id, version)
+    }
+
+    /// Host write of the data port, returning false if locked or the selected register does not
+    /// exist
+    pub fn write(&mut self, value: u8) -> bool {
+        if ! self.unlocked {
+            return false;
+        }
+        self.write_register(value)
+    }
+
+    /// EC2I read of the index (0) or data (1) port
+    pub fn ec2i_read(&self, port: u8, id: u16, version: u8) -> Option<u8> {
+        match port {
+            0 => Some(self.addr),
+            _ => self.read_register(id, version),
+        }
+    }
+
+    /// EC2I write of the index (0) or data (1) port, returning false if the selected register
+    /// does not exist
+    pub fn ec2i_write(&mut self, port: u8, value: u8) -> bool {
+        match port {
+            0 => {
+                self.addr = value;
+                true
+            },
+            _ => self.write_register(value),
+        }
+    }
+
+    fn read_register(&self, id: u16, version: u8) -> Option<u8> {
+        match self.addr {
+            0x07 => Some(self.ldn),
+            0x20 => Some((id >> 8) as u8),
+            0x21 => Some(id as u8),
+            0x22 => Some(version),
+            0x30 ..= 0xFF if self.device_register() => Some(self.register(self.ldn, self.addr)),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, value: u8) -> bool {
+        match self.addr {
+            0x02 => {
+                // Exit configuration mode
+                if value & (1 << 1) != 0 {
+                    self.unlocked = false;
+                }
+            },
+            0x07 => self.ldn = value,
+            0x30 ..= 0xFF if self.device_register() => {
+                self.devices[self.ldn as usize][self.addr as usize] = value;
+            },
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: MIT
+
+use area8051::{Addr, Mem};
+
+use crate::Ec;
+
+const TCON: u8 = 0x88;
+const TMOD: u8 = 0x89;
+const T2CON: u8 = 0xC8;
+
+const TCON_TR0: u8 = 1 << 4;
+const TCON_TF0: u8 = 1 << 5;
+const TCON_TR1: u8 = 1 << 6;
+const TCON_TF1: u8 = 1 << 7;
+
+const T2CON_TR2: u8 = 1 << 2;
+const T2CON_BAUD: u8 = 0b11 << 4;
+const T2CON_TF2: u8 = 1 << 7;
+
+/// Check if any timer is running
+pub fn timers_running(ec: &Ec) -> bool {
+    ec.load(Addr::Reg(TCON)) & (TCON_TR0 | TCON_TR1) != 0 ||
+    ec.load(Addr::Reg(T2CON)) & T2CON_TR2 != 0
+}
+
+/// Run timers for one machine cycle, returning the number of timer 1 and timer 2 overflows for
+/// use as baud rate clocks
+pub fn timers(ec: &mut Ec) -> (u8, u8) {
+    // Timer information from https://openlabpro.com/guide/timers-8051/
+    let mut tcon = ec.load(Addr::Reg(TCON));
+    let tmod = ec.load(Addr::Reg(TMOD));
+    let mut timer1 = 0;
+    let mut timer2 = 0;
+
+    // Timer 0 running
+    if tcon & TCON_TR0 != 0 {
+        assert!(tmod & 0x0F == 0x01, "unimplemented TMOD 0x{:02X}", tmod);
+
+        let tl = 0x8A;
+        let th = 0x8C;
+
+        let mut count =
+            ec.load(Addr::Reg(tl)) as u16 |
+            (ec.load(Addr::Reg(th)) as u16) << 8;
+
+        count = count.wrapping_add(1);
+
+        if count == 0 {
+            tcon |= TCON_TF0;
+            //TODO: implement timer 0 interrupts
+        }
+
+        ec.store(Addr::Reg(tl), count as u8);
+        ec.store(Addr::Reg(th), (count >> 8) as u8);
+    }
+
+    // Timer 1 running
+    if tcon & TCON_TR1 != 0 {
+        let tl = 0x8B;
+        let th = 0x8D;
+
+        match tmod & 0xF0 {
+            // 16-bit timer
+            0x10 => {
+                let mut count =
+                    ec.load(Addr::Reg(tl)) as u16 |
+                    (ec.load(Addr::Reg(th)) as u16) << 8;
+
+                count = count.wrapping_add(1);
+
+                if count == 0 {
+                    tcon |= TCON_TF1;
+                    timer1 += 1;
+                    //TODO: implement timer 1 interrupts
+                }
+
+                ec.store(Addr::Reg(tl), count as u8);
+                ec.store(Addr::Reg(th), (count >> 8) as u8);
+            },
+            // 8-bit auto-reload timer, usually the UART baud rate generator
+            0x20 => {
+                let (count, overflow) = ec.load(Addr::Reg(tl)).overflowing_add(1);
+
+                if overflow {
+                    tcon |= TCON_TF1;
+                    timer1 += 1;
+                    let reload = ec.load(Addr::Reg(th));
+                    ec.store(Addr::Reg(tl), reload);
+                } else {
+                    ec.store(Addr::Reg(tl), count);
+                }
+            },
+            _ => panic!("unimplemented TMOD 0x{:02X}", tmod),
+        }
+    }
+
+    // Timer 2 running
+    let mut t2con = ec.load(Addr::Reg(T2CON));
+    if t2con & T2CON_TR2 != 0 {
+        // Baud rate generator mode if RCLK or TCLK is set
+        let baud = t2con & T2CON_BAUD != 0;
+        assert!(baud || t2con & 0b11 == 0, "unimplemented T2CON 0x{:02X}", t2con);
+
+        let tl = 0xCC;
+        let th = 0xCD;
+
+        let mut count =
+            ec.load(Addr::Reg(tl)) as u16 |
+            (ec.load(Addr::Reg(th)) as u16) << 8;
+
+        // Baud rate generator counts at half the oscillator frequency
+        let increments = if baud { 6 } else { 1 };
+        for _ in 0..increments {
+            count = count.wrapping_add(1);
+
+            if count == 0 {
+                // Reload from RCAP2L and RCAP2H
+                count =
+                    ec.load(Addr::Reg(0xCA)) as u16 |
+                    (ec.load(Addr::Reg(0xCB)) as u16) << 8;
+
+                if baud {
+                    timer2 += 1;
+                } else {
+                    t2con |= T2CON_TF2;
+                    //TODO: implement timer 2 interrupts
+                }
+            }
+        }
+
+        ec.store(Addr::Reg(tl), count as u8);
+        ec.store(Addr::Reg(th), (count >> 8) as u8);
+
+        ec.store(Addr::Reg(T2CON), t2con);
+    }
+
+    ec.store(Addr::Reg(TCON), tcon);
+
+    (timer1, timer2)
+}
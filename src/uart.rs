@@ -0,0 +1,386 @@
+// SPDX-License-Identifier: MIT
+
+use area8051::{Addr, Mem};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::Ec;
+
+#[cfg(feature = "debug_uart")]
+macro_rules! debug {
+    ($($arg:tt)*) => (eprint!($($arg)*));
+}
+
+#[cfg(not(feature = "debug_uart"))]
+macro_rules! debug {
+    ($($arg:tt)*) => (());
+}
+
+const PCON: u8 = 0x87;
+const SCON: u8 = 0x98;
+const IE: u8 = 0xA8;
+const T2CON: u8 = 0xC8;
+
+const PCON_SMOD: u8 = 1 << 7;
+
+const SCON_RI: u8 = 1 << 0;
+const SCON_TI: u8 = 1 << 1;
+const SCON_RB8: u8 = 1 << 2;
+const SCON_REN: u8 = 1 << 4;
+
+const IE_ES: u8 = 1 << 4;
+const IE_EA: u8 = 1 << 7;
+
+const T2CON_TCLK: u8 = 1 << 4;
+const T2CON_RCLK: u8 = 1 << 5;
+
+/// Serial port interrupt number, vector 0x0023
+const INT_SERIAL: u8 = 4;
+
+/// Connection between the simulated serial port and the outside world
+pub trait UartIo: Send {
+    /// Read a received byte, if one is available. Must not block
+    fn read(&mut self) -> Option<u8>;
+
+    /// Write a transmitted byte
+    fn write(&mut self, byte: u8);
+}
+
+/// Transmit to stdout. Stdin is read by the `[ecsim]$` prompt, so nothing is received from it, and
+/// input is sent with the uart_write command instead. Use `pty` or `tcp` for an interactive
+/// console
+pub struct StdioUart;
+
+impl UartIo for StdioUart {
+    fn read(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn write(&mut self, byte: u8) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(&[byte]);
+        let _ = stdout.flush();
+    }
+}
+
+/// Transmit to a file, optionally receiving the contents of another file
+pub struct FileUart {
+    output: File,
+    input: Option<io::Bytes<File>>,
+}
+
+impl FileUart {
+    pub fn open(output_path: &str, input_path: Option<&str>) -> io::Result<Self> {
+        let output = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(output_path)?;
+        let input = match input_path {
+            Some(path) => Some(File::open(path)?.bytes()),
+            None => None,
+        };
+        Ok(Self { output, input })
+    }
+}
+
+impl UartIo for FileUart {
+    fn read(&mut self) -> Option<u8> {
+        self.input.as_mut()?.next()?.ok()
+    }
+
+    fn write(&mut self, byte: u8) {
+        let _ = self.output.write_all(&[byte]);
+    }
+}
+
+/// Accept a single client on a local TCP port
+pub struct TcpUart {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+}
+
+impl TcpUart {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            stream: None,
+        })
+    }
+
+    fn stream(&mut self) -> Option<&mut TcpStream> {
+        if self.stream.is_none() {
+            if let Ok((stream, addr)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_ok() {
+                    eprintln!("uart: connection from {}", addr);
+                    self.stream = Some(stream);
+                }
+            }
+        }
+        self.stream.as_mut()
+    }
+}
+
+impl UartIo for TcpUart {
+    fn read(&mut self) -> Option<u8> {
+        let stream = self.stream()?;
+        let mut buf = [0];
+        match stream.read(&mut buf) {
+            Ok(0) => {
+                eprintln!("uart: connection closed");
+                self.stream = None;
+                None
+            },
+            Ok(_) => Some(buf[0]),
+            Err(err) => {
+                if err.kind() != io::ErrorKind::WouldBlock {
+                    eprintln!("uart: connection failed: {}", err);
+                    self.stream = None;
+                }
+                None
+            }
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        if let Some(stream) = self.stream() {
+            if let Err(err) = stream.write_all(&[byte]) {
+                eprintln!("uart: connection failed: {}", err);
+                self.stream = None;
+            }
+        }
+    }
+}
+
+/// Pseudo-terminal, the slave side is opened by a terminal program
+#[cfg(unix)]
+pub struct PtyUart {
+    master: File,
+}
+
+#[cfg(unix)]
+impl PtyUart {
+    /// Create a pseudo-terminal, returning it along with the slave path
+    pub fn open() -> io::Result<(Self, String)> {
+        use std::ffi::CStr;
+        use std::os::unix::io::FromRawFd;
+
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+
+            if libc::grantpt(fd) < 0 || libc::unlockpt(fd) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let path = CStr::from_ptr(name).to_string_lossy().into_owned();
+
+            Ok((Self { master }, path))
+        }
+    }
+}
+
+#[cfg(unix)]
+impl UartIo for PtyUart {
+    fn read(&mut self) -> Option<u8> {
+        // Reads fail while no terminal has the slave side open
+        let mut buf = [0];
+        match self.master.read(&mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, byte: u8) {
+        let _ = self.master.write_all(&[byte]);
+    }
+}
+
+/// Open serial port connection from a description like `stdio`, `file:out.log[,in.txt]`,
+/// `tcp:8051`, or `pty`
+pub fn uart_io(spec: &str) -> io::Result<Box<dyn UartIo>> {
+    let mut parts = spec.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("stdio"), None) => Ok(Box::new(StdioUart)),
+        (Some("file"), Some(paths)) => {
+            let mut paths = paths.splitn(2, ',');
+            let output_path = paths.next().unwrap_or("");
+            Ok(Box::new(FileUart::open(output_path, paths.next())?))
+        },
+        (Some("tcp"), Some(port)) => {
+            let port = port.parse::<u16>().map_err(|err| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid uart port '{}': {}", port, err)
+            ))?;
+            eprintln!("uart: listening on 127.0.0.1:{}", port);
+            Ok(Box::new(TcpUart::bind(port)?))
+        },
+        #[cfg(unix)]
+        (Some("pty"), None) => {
+            let (pty, path) = PtyUart::open()?;
+            eprintln!("uart: {}", path);
+            Ok(Box::new(pty))
+        },
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown uart '{}'", spec)
+        )),
+    }
+}
+
+/// 8051 serial port state that does not live in SFRs
+pub struct Uart {
+    pub io: Box<dyn UartIo>,
+    /// Receive side of SBUF
+    pub rx_buffer: u8,
+    /// Bytes waiting to be received before any from io
+    pub rx_queue: VecDeque<u8>,
+    /// Byte being shifted out, and bit times left
+    tx: Option<(u8, u8)>,
+    /// Byte being shifted in, and bit times left
+    rx: Option<(u8, u8)>,
+    tx_clocks: u8,
+    rx_clocks: u8,
+}
+
+impl Uart {
+    pub fn new(io: Box<dyn UartIo>) -> Self {
+        Self {
+            io,
+            rx_buffer: 0,
+            rx_queue: VecDeque::new(),
+            tx: None,
+            rx: None,
+            tx_clocks: 0,
+            rx_clocks: 0,
+        }
+    }
+
+    /// Number of bit times in a frame, including start and stop bits
+    fn frame_bits(mode: u8) -> u8 {
+        match mode {
+            0 => 8,
+            1 => 10,
+            _ => 11,
+        }
+    }
+
+    /// Whether a bit time has passed during a machine cycle, using the clock source for the mode
+    fn bit_clock(clocks: &mut u8, mode: u8, smod: bool, timer2_selected: bool, timer1: u8, timer2: u8) -> bool {
+        let (ticks, divider) = match mode {
+            // Shift register, fosc / 12
+            0 => (1, 1),
+            // Fixed, fosc / 64 or fosc / 32, counted in oscillator periods
+            2 => (12, if smod { 32 } else { 64 }),
+            // Variable, from timer 2 or timer 1 overflows
+            _ => if timer2_selected {
+                (timer2, 16)
+            } else {
+                (timer1, if smod { 16 } else { 32 })
+            },
+        };
+
+        *clocks += ticks;
+        if *clocks >= divider {
+            *clocks -= divider;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Check if the serial port needs machine cycles to make progress
+    pub fn busy(&self, scon: u8) -> bool {
+        self.tx.is_some() || self.rx.is_some() || scon & SCON_REN != 0
+    }
+
+    /// Write to SBUF, starting transmission
+    pub fn transmit(&mut self, scon: u8, value: u8) {
+        debug!("\n[uart transmit 0x{:02X}]", value);
+        self.tx = Some((value, Self::frame_bits(scon >> 6)));
+        self.tx_clocks = 0;
+    }
+
+    /// Advance by one machine cycle, given timer overflows during it, returning the new SCON value
+    pub fn step(&mut self, mut scon: u8, pcon: u8, t2con: u8, timer1: u8, timer2: u8) -> u8 {
+        let mode = scon >> 6;
+        let smod = pcon & PCON_SMOD != 0;
+
+        let tx_bit = Self::bit_clock(&mut self.tx_clocks, mode, smod, t2con & T2CON_TCLK != 0, timer1, timer2);
+        if tx_bit {
+            if let Some((byte, bits)) = self.tx.take() {
+                if bits > 1 {
+                    self.tx = Some((byte, bits - 1));
+                } else {
+                    debug!("\n[uart transmitted 0x{:02X}]", byte);
+                    self.io.write(byte);
+                    scon |= SCON_TI;
+                }
+            }
+        }
+
+        let rx_bit = Self::bit_clock(&mut self.rx_clocks, mode, smod, t2con & T2CON_RCLK != 0, timer1, timer2);
+        if rx_bit {
+            match self.rx.take() {
+                Some((byte, bits)) => if bits > 1 {
+                    self.rx = Some((byte, bits - 1));
+                } else if scon & SCON_RI == 0 {
+                    debug!("\n[uart received 0x{:02X}]", byte);
+                    self.rx_buffer = byte;
+                    scon |= SCON_RI;
+                    if mode != 0 {
+                        // Stop bit or ninth bit
+                        scon |= SCON_RB8;
+                    }
+                } else {
+                    debug!("\n[uart overrun 0x{:02X}]", byte);
+                },
+                None => if scon & SCON_REN != 0 && (mode != 0 || scon & SCON_RI == 0) {
+                    let next = match self.rx_queue.pop_front() {
+                        Some(byte) => Some(byte),
+                        None => self.io.read(),
+                    };
+                    if let Some(byte) = next {
+                        self.rx = Some((byte, Self::frame_bits(mode)));
+                    }
+                },
+            }
+        }
+
+        scon
+    }
+}
+
+/// Run the serial port for one machine cycle, given timer overflows to use as baud rate clocks.
+/// Returns true if the serial port needs more machine cycles
+pub fn uart(ec: &mut Ec, timer1: u8, timer2: u8) -> bool {
+    let scon = ec.load(Addr::Reg(SCON));
+    let pcon = ec.load(Addr::Reg(PCON));
+    let t2con = ec.load(Addr::Reg(T2CON));
+
+    let (new_scon, busy) = {
+        let mut uart = ec.uart.lock().unwrap();
+        let new_scon = uart.step(scon, pcon, t2con, timer1, timer2);
+        (new_scon, uart.busy(new_scon))
+    };
+    if new_scon != scon {
+        ec.store(Addr::Reg(SCON), new_scon);
+    }
+
+    let ie = ec.load(Addr::Reg(IE));
+    if new_scon & (SCON_RI | SCON_TI) != 0 && ie & IE_EA != 0 && ie & IE_ES != 0 && ec.isr.is_none() {
+        ec.interrupt(INT_SERIAL);
+    }
+
+    busy
+}
@@ -3,6 +3,10 @@
 use area8051::{Addr, Mem};
 
 use crate::Ec;
+use crate::espi::vw_host_driven;
+use crate::host::Notification;
+use crate::smfi;
+use crate::superio::{LDN_KEYBOARD, LDN_MOUSE};
 
 #[cfg(feature = "debug_xram")]
 macro_rules! debug {
@@ -16,12 +20,13 @@ macro_rules! debug {
 
 pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
     let mut mcu = ec.mcu.lock().unwrap();
-    let mut spi = ec.spi.lock().unwrap();
     let mut xmem = ec.xmem.lock().unwrap();
 
     debug!("\n[xram 0x{:04X}", address);
 
-    let mut old = mcu.load(Addr::XRam(address));
+    // SRAM aliases share storage
+    let stored = Addr::XRam(ec.sram(address as usize) as u16);
+    let mut old = mcu.load(stored);
 
     // Bit masks for register access: Default is R/W
     let mut write_clear_mask = 0;
@@ -33,9 +38,11 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
         0x0000 ..= 0x0FFF => {
             debug!(" (SRAM)");
         },
-        0x8000 ..= 0x97FF if ec.id == 0x5570 => {
+        0x8000 ..= 0x8FFF if ec.id == 0x5570 => {
+            debug!(" (SRAM 0x{:04X})", ec.sram(address as usize));
+        },
+        0x9000 ..= 0x97FF if ec.id == 0x5570 => {
             debug!(" (SRAM)");
-            //TODO: SRAM is double mapped from 0x8000 - 0x8FFF
         },
         // SMFI
         0x1000 ..= 0x10FF => {
@@ -58,20 +65,31 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
 
                 debug!(" [SCAR{} DMA 0x{:04X} = 0x{:04X}]", scar, base, value);
                 for i in 0..size {
-                    mcu.xram[base + i] = mcu.pmem[value + i];
+                    mcu.xram[ec.sram(base + i)] = mcu.pmem[value + i];
                 }
             };
 
             match offset {
                 0x00 => debug!(" FBCFG"),
                 0x01 => debug!(" FPCFG"),
-                0x05 => debug!(" FECBSR"),
-                0x07 => debug!(" FMSSR"),
+                0x05 => {
+                    debug!(" FECBSR");
+                    read_only_mask = 0b1111_1100;
+                }
+                0x07 => {
+                    debug!(" FMSSR");
+                    read_only_mask = 0b1111_0000;
+                }
                 0x20 => {
                     debug!(" SMECCS");
                     write_clear_mask = 0b0100_0000;
                     read_only_mask = 0b0001_1000;
                 }
+                0x31 => {
+                    debug!(" FLHCTRL1R");
+                    // The protect lock can only be set
+                    read_only_mask = old & smfi::FLHCTRL1R_PLOCK;
+                }
                 0x32 => debug!(" FLHCTRL2R"),
                 0x33 => debug!(" CACHDISR"),
                 0x36 => debug!(" HCTRL2R"),
@@ -98,46 +116,68 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
                     };
 
                     debug!(" [flash address 0x{:08X}", a);
-                    let (flash, flash_name): (&mut [u8], &str) = match (a3 >> 6) & 0b11 {
+                    let pc = mcu.pc;
+                    let i = a & 0xFFFFFF;
+                    // FMSSR and the protect regions only apply to the internal flash decoded by SMFI
+                    let internal_size = smfi::flash_size(&mcu.xram, mcu.pmem.len());
+                    let external_size = xmem.len();
+                    // ECINDAR3 bits 7:6 select the access type
+                    let target: Option<(&mut [u8], &str, usize, usize, bool, _)> = match (a3 >> 6) & 0b11 {
                         0b00 | 0b11 => {
-                            (&mut xmem, "external")
+                            Some((&mut xmem[..], "external", 1, external_size, false, ec.spi_external.lock().unwrap()))
                         },
                         0b01 => {
-                            (&mut mcu.pmem, "internal")
+                            let write_protected = smfi::protected(&mcu.xram, i, smfi::PZR_EC_WRITE);
+                            Some((&mut mcu.pmem[..], "internal", 0, internal_size, write_protected, ec.spi.lock().unwrap()))
                         },
-                        unknown => {
-                            panic!("unknown ECIND flash chip 0b{:02b}", unknown);
-                        }
+                        _ => None,
                     };
-                    debug!(" ({})]", flash_name);
 
-                    if a3 & 0xF == 0xF {
-                        match a1 {
-                            0xFD => {
-                                // Enable chip, send or receive
-                                debug!(" [follow enable]");
-                                if let Some(new) = new_opt {
-                                    spi.input.push_back(new);
+                    if let Some((flash, flash_name, chip, flash_size, write_protected, mut spi)) = target {
+                        debug!(" ({})]", flash_name);
+                        spi.now = ec.steps;
+
+                        if a3 & 0xF == 0xF {
+                            match a1 {
+                                0xFD => {
+                                    // Enable chip, send or receive
+                                    debug!(" [follow enable]");
+                                    if let Some(new) = new_opt {
+                                        spi.input.push_back(new);
+                                    } else {
+                                        old = spi.read(flash, flash_name);
+                                    }
+                                },
+                                0xFE => {
+                                    // Disable chip
+                                    debug!(" [follow disable]");
+                                    spi.deselect(flash, flash_name);
+                                },
+                                _ => {
+                                    debug!(" [unknown follow address]");
+                                    spi.error(format!("unknown follow address 0x{:02X}", a1));
+                                    old = 0xFF;
+                                }
+                            }
+                            crate::flash::record_spi(ec, chip, &mut spi, "ecindar follow", pc);
+                        } else if i >= flash_size {
+                            debug!(" [outside of flash]");
+                            old = 0xFF;
+                        } else {
+                            old = flash[i];
+                            if let Some(new) = new_opt {
+                                if write_protected {
+                                    debug!(" [protected]");
+                                    spi.error(format!("ECINDDR write to protected 0x{:06X} blocked", i));
                                 } else {
-                                    spi.step(flash, flash_name);
-                                    old = spi.output.pop_front().expect("tried to read missing flash follow output");
+                                    flash[i] = new;
+                                    crate::flash::record(ec, chip, i, 1, "ecindar", "write", pc);
                                 }
-                            },
-                            0xFE => {
-                                // Disable chip
-                                debug!(" [follow disable]");
-                                spi.step(flash, flash_name);
-                            },
-                            _ => {
-                                panic!("Unknown follow address 0x{:02X}", a1);
                             }
                         }
                     } else {
-                        let i = a & 0xFFFFFF;
-                        old = flash[i];
-                        if let Some(new) = new_opt {
-                            flash[i] = new;
-                        }
+                        debug!(" (reserved)]");
+                        old = 0xFF;
                     }
                 },
                 0x40 => debug!(" SCAR0L"),
@@ -204,6 +244,16 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
                 0x5D => debug!(" HRAMW0AAS"),
                 0x5E => debug!(" HRAMW1AAS"),
                 0x63 => debug!(" FLHCTRL3R"),
+                0x70 ..= 0x75 => {
+                    match (offset - 0x70) % 3 {
+                        0 => debug!(" P{}BA0R", (offset - 0x70) / 3),
+                        1 => debug!(" P{}BA1R", (offset - 0x70) / 3),
+                        _ => debug!(" P{}ZR", (offset - 0x70) / 3),
+                    }
+                    if mcu.xram[smfi::FLHCTRL1R] & smfi::FLHCTRL1R_PLOCK != 0 {
+                        read_only_mask = 0b1111_1111;
+                    }
+                }
                 _ => panic!("xram unimplemented SMFI register 0x{:02X}", offset)
             }
             debug!(")");
@@ -334,14 +384,34 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
             let base = 0x1200;
             let offset = address - base;
             debug!(" (E2CI 0x{:02X}", offset);
+            // CFGAE in IBMAE and CSAE in IBCTL enable EC2I access to the SuperIO configuration
+            let ec2i_enabled = mcu.xram[0x1204] & 1 != 0 && mcu.xram[0x1205] & 1 != 0;
             match offset {
                 0x00 => debug!(" IHIOA"),
-                0x01 => debug!(" IHD"),
+                0x01 => {
+                    debug!(" IHD");
+                    if let Some(new) = new_opt {
+                        if ec2i_enabled {
+                            let port = mcu.xram[0x1200] & 1;
+                            if ! ec.superio.lock().unwrap().ec2i_write(port, new) {
+                                debug!(" (unimplemented)");
+                            }
+                        }
+                    }
+                },
                 0x02 => debug!(" LSIOHA"),
                 0x04 => debug!(" IBMAE"),
                 0x05 => {
                     debug!(" IBCTL");
-                    read_only_mask = 0b0000_0100;
+                    // CRIB reads into IHD, and completes immediately
+                    if let Some(new) = new_opt {
+                        if new & (1 << 1) != 0 && new & 1 != 0 && mcu.xram[0x1204] & 1 != 0 {
+                            let port = mcu.xram[0x1200] & 1;
+                            let value = ec.superio.lock().unwrap().ec2i_read(port, ec.id, ec.version);
+                            mcu.xram[0x1201] = value.unwrap_or(0xFF);
+                        }
+                    }
+                    read_only_mask = 0b0000_0110;
                 }
                 _ => panic!("xram unimplemented E2CI register 0x{:02X}", offset)
             }
@@ -364,14 +434,31 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
                 }
                 0x06 => {
                     debug!(" KBHIKDOR");
-                    // Set output buffer full flag
-                    mcu.xram[0x1304] |= 1 << 0;
+                    if new_opt.is_some() {
+                        // Set output buffer full flag, holding keyboard data
+                        mcu.xram[0x1304] |= 1 << 0;
+                        mcu.xram[0x1304] &= !(1 << 5);
+                        // Keyboard IRQ, normally IRQ1, if OBFKIE is set
+                        if mcu.xram[0x1300] & (1 << 0) != 0 {
+                            if let Some(irq) = ec.superio.lock().unwrap().irq(LDN_KEYBOARD) {
+                                ec.notifications.lock().unwrap().push(Notification::Irq(irq));
+                            }
+                        }
+                    }
                     write_only_mask = 0b1111_1111;
                 },
                 0x08 => {
                     debug!(" KBHIMDOR");
-                    // Set output buffer full flag
-                    mcu.xram[0x1304] |= 1 << 0;
+                    if new_opt.is_some() {
+                        // Set output buffer full flag, holding mouse data
+                        mcu.xram[0x1304] |= (1 << 5) | (1 << 0);
+                        // Mouse IRQ, normally IRQ12, if OBFMIE is set
+                        if mcu.xram[0x1300] & (1 << 1) != 0 {
+                            if let Some(irq) = ec.superio.lock().unwrap().irq(LDN_MOUSE) {
+                                ec.notifications.lock().unwrap().push(Notification::Irq(irq));
+                            }
+                        }
+                    }
                     write_only_mask = 0b1111_1111;
                 },
                 0x0A => {
@@ -401,29 +488,57 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
             let base = 0x1500;
             let offset = address - base;
             debug!(" (PMC 0x{:02X}", offset);
+
+            // Status register of the channel
+            let sts = (base + (offset & 0xF0)) as usize;
+
             match offset {
-                0x00 => {
-                    debug!(" PM1STS");
+                0x00 | 0x10 | 0x20 | 0x30 | 0x40 => {
+                    debug!(" PM{}STS", (offset >> 4) + 1);
                     read_only_mask = 0b0000_1011;
                 }
-                0x01 => {
-                    debug!(" PM1DO");
-                    // Set output buffer full flag
-                    mcu.xram[0x1500] |= 1 << 0;
+                0x01 | 0x11 | 0x21 | 0x31 | 0x41 => {
+                    debug!(" PM{}DO", (offset >> 4) + 1);
+                    if new_opt.is_some() {
+                        // Set output buffer full flag
+                        mcu.xram[sts] |= 1 << 0;
+                    }
                     write_only_mask = 0b1111_1111;
                 },
-                0x04 => {
-                    debug!(" PM1DI");
+                0x02 | 0x12 => {
+                    debug!(" PM{}DOSCI", (offset >> 4) + 1);
+                    if new_opt.is_some() {
+                        // Set output buffer full flag and generate SCI
+                        mcu.xram[sts] |= 1 << 0;
+                        ec.sci("pmc");
+                    }
+                    write_only_mask = 0b1111_1111;
+                },
+                0x03 | 0x13 => {
+                    debug!(" PM{}DOSMI", (offset >> 4) + 1);
+                    if new_opt.is_some() {
+                        // Set output buffer full flag and generate SMI
+                        mcu.xram[sts] |= 1 << 0;
+                        ec.notifications.lock().unwrap().push(Notification::Smi);
+                    }
+                    write_only_mask = 0b1111_1111;
+                },
+                0x04 | 0x14 | 0x22 | 0x32 | 0x42 => {
+                    debug!(" PM{}DI", (offset >> 4) + 1);
                     // Clear input buffer full flag
-                    mcu.xram[0x1500] &= !(1 << 1);
+                    mcu.xram[sts] &= !(1 << 1);
                     read_only_mask = 0b1111_1111;
                 }
-                0x06 => debug!(" PM1CTL"),
-                0x16 => debug!(" PM2CTL"),
-                0x30 => {
-                    debug!(" PM4STS");
-                    read_only_mask = 0b0000_1011;
+                0x05 | 0x15 => {
+                    debug!(" PM{}DISCI", (offset >> 4) + 1);
+                    // Clear input buffer full flag
+                    mcu.xram[sts] &= !(1 << 1);
+                    read_only_mask = 0b1111_1111;
                 }
+                0x06 | 0x16 | 0x23 | 0x33 | 0x43 => debug!(" PM{}CTL", (offset >> 4) + 1),
+                0x07 | 0x17 | 0x24 | 0x34 | 0x44 => debug!(" PM{}IC", (offset >> 4) + 1),
+                0x08 | 0x18 | 0x25 | 0x35 | 0x45 => debug!(" PM{}IE", (offset >> 4) + 1),
+                0x19 => debug!(" MBXCTRL"),
                 _ => panic!("xram unimplemented PMC register 0x{:02X}", offset)
             }
             debug!(")");
@@ -433,6 +548,24 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
             let base = 0x1600;
             let offset = address - base;
             debug!(" (GPIO 0x{:02X}", offset);
+
+            // SCI# is GPD3, as wired to the PCH on System76 boards, and raises an SCI when driven low
+            if let Some(new) = new_opt {
+                if offset == 0x04 || offset == 0x2B {
+                    let asserted = |data: u8, control: u8| data & (1 << 3) == 0 && control & 0xC0 == 0x40;
+                    let data = mcu.xram[0x1604];
+                    let control = mcu.xram[0x162B];
+                    let (new_data, new_control) = if offset == 0x04 {
+                        (new, control)
+                    } else {
+                        (data, new)
+                    };
+                    if ! asserted(data, control) && asserted(new_data, new_control) {
+                        ec.sci("gpio");
+                    }
+                }
+            }
+
             match offset {
                 0x00 => debug!(" GCR"),
 
@@ -978,6 +1111,16 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
                     debug!(" General Capabilities and Configurations 0");
                     read_only_mask = 0b1111_1111;
                 }
+                0x08 ..= 0x13 => {
+                    debug!(" Channel {} Capabilities and Configurations {}", (offset - 0x08) / 4, 3 - (offset & 3));
+                    // Channel enable is set by the host, and peripheral and virtual wire channel ready
+                    // follow it. OOB channel ready is set by firmware
+                    read_only_mask = match offset {
+                        0x0B | 0x0F => 0b0000_0011,
+                        0x13 => 0b0000_0001,
+                        _ => 0,
+                    };
+                }
                 0x14 => debug!(" Channel 3 Capabilities and Configurations 3"),
                 0x15 => debug!(" Channel 3 Capabilities and Configurations 2"),
                 0x16 => {
@@ -986,7 +1129,16 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
                 }
                 0x17 => {
                     debug!(" Channel 3 Capabilities and Configurations 0");
-                    read_only_mask = 0b1111_1111;
+                    // Flash channel ready is set by firmware
+                    read_only_mask = 0b1111_1101;
+                }
+                0x18 => debug!(" Channel 3 Capabilities and Configurations 2-3"),
+                0x19 => debug!(" Channel 3 Capabilities and Configurations 2-2"),
+                0x1A => debug!(" Channel 3 Capabilities and Configurations 2-1"),
+                0x1B => debug!(" Channel 3 Capabilities and Configurations 2-0"),
+                0xA0 => {
+                    debug!(" ESGCTRL0");
+                    write_clear_mask = 0b0000_1111;
                 }
                 0xA1 => debug!(" ESGCTRL1"),
                 0xA2 => debug!(" ESGCTRL2"),
@@ -1002,16 +1154,35 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
                 0xC1 => debug!(" ESOCTRL1"),
                 0xC4 => debug!(" ESOCTRL4"),
                 // Virtual wire
+                0x100 | 0x101 => debug!(" VW index 0x{:02X}", offset - 0x100),
+                0x102 ..= 0x107 | 0x140 ..= 0x147 => {
+                    let index = (offset - 0x100) as u8;
+                    debug!(" VW index 0x{:02X}", index);
+                    if vw_host_driven(index) {
+                        read_only_mask = 0b1111_1111;
+                    } else if let Some(new) = new_opt {
+                        // Send changed wires with valid bits set to the host
+                        let valid = new >> 4;
+                        if (old ^ new) & 0xF0 != 0 || (old ^ new) & valid != 0 {
+                            ec.notifications.lock().unwrap().push(Notification::VirtualWire(index, new));
+                        }
+                        // SCI# and SMI# are asserted low
+                        if index == 0x06 {
+                            let asserted = |value: u8, shift: u8| value & (0x11 << shift) == 0x10 << shift;
+                            if ! asserted(old, 0) && asserted(new, 0) {
+                                ec.sci("espi");
+                            }
+                            if ! asserted(old, 1) && asserted(new, 1) {
+                                ec.notifications.lock().unwrap().push(Notification::Smi);
+                            }
+                        }
+                    }
+                },
                 0x190 => debug!(" VWCTRL0"),
                 _ => panic!("xram unimplemented eSPI register 0x{:02X}", offset)
             }
             debug!(")");
         },
-        0x8000 ..= 0x97FF if ec.id == 0x5570 => {
-            let base = 0x8000;
-            let _offset = address - base;
-            debug!(" (SRAM 0x{:02X})", _offset);
-        }
         _ => panic!("xram unimplemented register 0x{:04X}", address),
     }
 
@@ -1024,7 +1195,7 @@ pub fn xram(ec: &Ec, address: u16, new_opt: Option<u8>) -> u8 {
         let ro = old | (new & !read_only_mask);
         let value = rwc & ro;
 
-        mcu.store(Addr::XRam(address), value);
+        mcu.store(stored, value);
     }
 
     debug!("]");
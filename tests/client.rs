@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: MIT
+
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use ecsim::{Ec, xram};
+use ecsim::client::{Client, ClientError};
+use ecsim::protocol::{self, Frame, Outcome, PAYLOAD_MAX};
+
+/// Serve one client from a thread, answering requests like the host interface does, and return
+/// the Ec once the client disconnects
+fn serve(mut ec: Ec) -> (Client<TcpStream>, JoinHandle<Ec>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        while let Ok(request) = Frame::read(&mut stream) {
+            match protocol::execute(&mut ec, 0, &request) {
+                Outcome::Done(response) => stream.write_all(&response.to_bytes().unwrap()).unwrap(),
+                Outcome::Wait(_) => panic!("waits are not served"),
+            }
+        }
+        ec
+    });
+    let client = Client::connect_tcp(addr, Duration::from_secs(10)).unwrap();
+    (client, server)
+}
+
+fn it5570() -> Ec {
+    Ec::from_rom(0x5570, 0x01, Vec::new())
+}
+
+#[test]
+fn request_and_response() {
+    let (mut client, server) = serve(it5570());
+
+    // SuperIO chip ID
+    client.outb(0x2E, 0x20).unwrap();
+    assert_eq!(client.inb(0x2F).unwrap(), 0x55);
+
+    // Errors reported by the simulator leave the connection usable
+    assert!(matches!(client.inb(0x1234), Err(ClientError::Status(protocol::STATUS_UNIMPLEMENTED))));
+    assert!(! client.broken());
+    client.outb(0x2E, 0x21).unwrap();
+    assert_eq!(client.inb(0x2F).unwrap(), 0x70);
+
+    drop(client);
+    server.join().unwrap();
+}
+
+#[test]
+fn memory_writes_larger_than_a_frame() {
+    let ec = it5570();
+    // SMECCS HOSTWA allows host flash writes
+    xram(&ec, 0x1020, Some(0x20));
+    let (mut client, server) = serve(ec);
+
+    let data: Vec<u8> = (0..PAYLOAD_MAX + 4096).map(|i| (i % 251) as u8).collect();
+    client.write_mem(0xFFFE_0000, &data).unwrap();
+
+    let mut read = vec![0; data.len()];
+    client.read_mem(0xFFFE_0000, &mut read).unwrap();
+    assert_eq!(read, data);
+
+    drop(client);
+    let ec = server.join().unwrap();
+    assert_eq!(&ec.mcu.lock().unwrap().pmem[..data.len()], data.as_slice());
+}
+
+#[test]
+fn timeout_breaks_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+
+        // Answer HELLO
+        let hello = Frame::read(&mut stream).unwrap();
+        let response = Frame {
+            version: protocol::VERSION,
+            kind: hello.kind | protocol::RESPONSE,
+            id: hello.id,
+            payload: vec![protocol::STATUS_OK, protocol::VERSION],
+        };
+        stream.write_all(&response.to_bytes().unwrap()).unwrap();
+
+        // Start the next response, but stop in the middle of its header
+        let request = Frame::read(&mut stream).unwrap();
+        stream.write_all(&[protocol::VERSION, request.kind | protocol::RESPONSE]).unwrap();
+
+        // Keep the connection open until the client is done
+        let _ = Frame::read(&mut stream);
+    });
+
+    let mut client = Client::connect_tcp(addr, Duration::from_millis(100)).unwrap();
+    assert!(matches!(client.inb(0x62), Err(ClientError::Timeout)));
+    assert!(client.broken());
+
+    // The rest of the stream can not be trusted, so later requests fail without being sent
+    assert!(matches!(client.inb(0x62), Err(ClientError::Broken)));
+
+    drop(client);
+    server.join().unwrap();
+}
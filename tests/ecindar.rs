@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::{Ec, xram};
+
+/// Set the flash address of ECINDDR, with the access type in bits 7:6 of ECINDAR3
+fn ecindar(ec: &Ec, address: u32) {
+    for (i, value) in address.to_le_bytes().iter().enumerate() {
+        xram(ec, 0x103B + i as u16, Some(*value));
+    }
+}
+
+/// Read flash through ECINDAR and ECINDDR
+fn ecinddr(ec: &Ec, address: u32) -> u8 {
+    ecindar(ec, address);
+    xram(ec, 0x103F, None)
+}
+
+#[test]
+fn external_flash_is_not_limited_by_fmssr() {
+    let pmem = vec![0xFF; 128 * 1024];
+    let mut xmem = vec![0xFF; 2 * 1024 * 1024];
+    xmem[0x1F_0000] = 0x5A;
+    let ec = Ec::from_images(0x5570, 0x01, pmem, xmem);
+
+    assert_eq!(ecinddr(&ec, 0x001F_0000), 0x5A);
+}
+
+#[test]
+fn internal_flash_is_limited_by_fmssr() {
+    let mut pmem = vec![0xFF; 256 * 1024];
+    pmem[0x2_0000] = 0x5A;
+    let xmem = vec![0xFF; 2 * 1024 * 1024];
+    let ec = Ec::from_images(0x5570, 0x01, pmem, xmem);
+
+    // FMSSR decodes 128 KiB after reset
+    assert_eq!(ecinddr(&ec, 0x4002_0000), 0xFF);
+    xram(&ec, 0x1007, Some(0x02));
+    assert_eq!(ecinddr(&ec, 0x4002_0000), 0x5A);
+}
+
+#[test]
+fn protect_regions_do_not_block_external_writes() {
+    let pmem = vec![0xFF; 128 * 1024];
+    let xmem = vec![0xFF; 2 * 1024 * 1024];
+    let ec = Ec::from_images(0x5570, 0x01, pmem, xmem);
+
+    // Protect region 0 blocks EC writes to the first 4 KiB of internal flash
+    xram(&ec, 0x1070, Some(0x00));
+    xram(&ec, 0x1071, Some(0x00));
+    xram(&ec, 0x1072, Some(0x40));
+
+    ecindar(&ec, 0x4000_0100);
+    xram(&ec, 0x103F, Some(0x12));
+    assert_eq!(ecinddr(&ec, 0x4000_0100), 0xFF);
+
+    ecindar(&ec, 0x0000_0100);
+    xram(&ec, 0x103F, Some(0x34));
+    assert_eq!(ecinddr(&ec, 0x0000_0100), 0x34);
+    assert_eq!(ec.xmem.lock().unwrap()[0x100], 0x34);
+}
@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::{Ec, xram};
+use ecsim::espi::{self, CONFIG_OFFSETS};
+use ecsim::host::HostError;
+
+/// ESGCTRL0, with a status bit per channel
+const ESGCTRL0: usize = 0x31A0;
+
+fn it5570() -> Ec {
+    Ec::from_rom(0x5570, 0x01, Vec::new())
+}
+
+fn configs(ec: &Ec) -> Vec<u32> {
+    CONFIG_OFFSETS.iter().map(|&offset| espi::get_configuration(ec, offset).unwrap()).collect()
+}
+
+#[test]
+fn reset_defaults() {
+    let ec = it5570();
+    assert_eq!(configs(&ec), vec![0x0302_000F, 0x0000_1100, 0x0000_0700, 0x0000_0110, 0x0000_1124]);
+    for index in (0x02..=0x07).chain(0x40..=0x47) {
+        assert_eq!(espi::vw_get(&ec, index), Ok(0x03), "index 0x{:02X}", index);
+    }
+}
+
+#[test]
+fn espi_reset_restores_defaults() {
+    let mut ec = it5570();
+    let defaults = configs(&ec);
+
+    // Enabling the virtual wire channel makes it ready
+    espi::set_configuration(&mut ec, 0x20, 0x0000_0001).unwrap();
+    assert_eq!(espi::get_configuration(&ec, 0x20), Ok(0x0000_0703));
+    assert_eq!(ec.mcu.lock().unwrap().xram[ESGCTRL0], 1 << 1);
+    espi::vw_set(&mut ec, 0x02, 0x10).unwrap();
+
+    // Firmware clears the status, and the reset reports the enabled channel again
+    ec.mcu.lock().unwrap().xram[ESGCTRL0] = 0;
+    espi::espi_reset(&mut ec).unwrap();
+    assert_eq!(configs(&ec), defaults);
+    assert_eq!(ec.mcu.lock().unwrap().xram[ESGCTRL0], 1 << 1);
+    assert_eq!(espi::vw_get(&ec, 0x02), Ok(0x03));
+}
+
+#[test]
+fn host_sets_valid_wires() {
+    let mut ec = it5570();
+
+    // SLP_S4# valid and low, leaving SLP_S3# untouched
+    espi::vw_set(&mut ec, 0x02, 0x20).unwrap();
+    assert_eq!(espi::vw_get(&ec, 0x02), Ok(0x21));
+
+    // SLP_S3# valid and high
+    espi::vw_set(&mut ec, 0x02, 0x11).unwrap();
+    assert_eq!(espi::vw_get(&ec, 0x02), Ok(0x31));
+    assert_eq!(xram(&ec, 0x3202, None), 0x31);
+}
+
+#[test]
+fn wires_have_one_driver() {
+    let mut ec = it5570();
+
+    // The host can not drive SCI# and SMI#
+    assert_eq!(espi::vw_set(&mut ec, 0x06, 0x10), Err(HostError::Protected));
+    assert_eq!(espi::vw_get(&ec, 0x06), Ok(0x03));
+
+    // Firmware can not drive SLP_S3#
+    xram(&ec, 0x3202, Some(0x10));
+    assert_eq!(espi::vw_get(&ec, 0x02), Ok(0x03));
+
+    // Indexes without wires
+    assert_eq!(espi::vw_get(&ec, 0x08), Err(HostError::Unimplemented));
+    assert_eq!(espi::vw_set(&mut ec, 0x48, 0x10), Err(HostError::Unimplemented));
+}
+
+#[test]
+fn espi_needs_it5570() {
+    let mut ec = Ec::from_rom(0x8587, 0x06, Vec::new());
+    assert_eq!(espi::get_configuration(&ec, 0x08), Err(HostError::Unimplemented));
+    assert_eq!(espi::set_configuration(&mut ec, 0x20, 1), Err(HostError::Unimplemented));
+    assert_eq!(espi::espi_reset(&mut ec), Err(HostError::Unimplemented));
+    assert_eq!(espi::vw_get(&ec, 0x02), Err(HostError::Unimplemented));
+}
@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::Ec;
+use ecsim::flash::{self, diff, record};
+
+fn it5570() -> Ec {
+    Ec::from_rom(0x5570, 0x01, Vec::new())
+}
+
+#[test]
+fn every_change_of_a_page_is_kept() {
+    let mut ec = it5570();
+    record(&ec, 0, 0x100, 1, "ecindar", "write", 0x1234);
+    // Another byte of the same write is counted with it
+    record(&ec, 0, 0x101, 1, "ecindar", "write", 0x1234);
+    ec.steps = 10;
+    record(&ec, 0, 0x180, 1, "host", "write", 0x5678);
+
+    let changes = ec.flash.changes.lock().unwrap();
+    let history = &changes[&(0, 0x100)];
+    assert_eq!(history.len(), 2);
+    assert_eq!((history[0].path, history[0].writes, history[0].pc), ("ecindar", 2, 0x1234));
+    assert_eq!((history[1].path, history[1].step, history[1].pc), ("host", 10, 0x5678));
+}
+
+#[test]
+fn changes_per_page_are_capped() {
+    let mut ec = it5570();
+    for step in 0..1000 {
+        ec.steps = step;
+        record(&ec, 0, 0x100, 1, "host", "write", 0);
+    }
+
+    let changes = ec.flash.changes.lock().unwrap();
+    let history = &changes[&(0, 0x100)];
+    assert!(history.len() < 1000);
+    assert_eq!(history.back().unwrap().step, 999);
+}
+
+#[test]
+fn diff_is_unified() {
+    let ec = it5570();
+    ec.mcu.lock().unwrap().pmem[0x105] = 0x00;
+
+    let text = diff(&ec);
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "--- a/internal");
+    assert_eq!(lines[1], "+++ b/internal");
+    // Row 0x10 is line 17, with 3 lines of context on each side
+    assert_eq!(lines[2], "@@ -14,7 +14,7 @@");
+    assert!(lines[3].starts_with(" 0000D0:"));
+    assert!(lines[6].starts_with("-000100: FF FF FF FF FF FF"));
+    assert!(lines[7].starts_with("+000100: FF FF FF FF FF 00"));
+    assert_eq!(lines.len(), 2 + 1 + 7 + 1);
+
+    flash::revert(&ec);
+    assert!(diff(&ec).is_empty());
+}
@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::{Sim, xram};
+use ecsim::host::HostError;
+
+/// Open H2RAM window 0 at 0x100 for 64 bytes, with memory or I/O cycles
+fn sim(io: bool) -> Sim {
+    let sim = Sim::from_rom(Vec::new());
+    // HRAMWC: window 0, and I/O cycles
+    xram(&sim.ec, 0x105A, Some(if io { 0x11 } else { 0x01 }));
+    // HRAMW0BA: 0x100
+    xram(&sim.ec, 0x105B, Some(0x10));
+    // HRAMW0AAS: 64 bytes
+    xram(&sim.ec, 0x105D, Some(0x02));
+    sim
+}
+
+#[test]
+fn memory_window_maps_sram() {
+    let mut sim = sim(false);
+    assert_eq!(sim.writeb(0x104, 0x5A), Ok(()));
+    assert_eq!(xram(&sim.ec, 0x0104, None), 0x5A);
+    xram(&sim.ec, 0x0105, Some(0xA5));
+    assert_eq!(sim.readb(0x105), Ok(0xA5));
+
+    // Outside of the window, memory cycles go to flash, which does not decode low addresses
+    assert_eq!(sim.readb(0x140), Err(HostError::Unimplemented));
+    // I/O cycles are not decoded while the window uses memory cycles
+    assert_eq!(sim.inb(0x105), Err(HostError::Unimplemented));
+}
+
+#[test]
+fn write_protected_window_rejects_writes() {
+    let mut sim = sim(false);
+    xram(&sim.ec, 0x0104, Some(0x11));
+
+    // HRAMW0AAS bit 4 protects from host writes
+    xram(&sim.ec, 0x105D, Some(0x12));
+    assert_eq!(sim.writeb(0x104, 0x5A), Err(HostError::Protected));
+    assert_eq!(xram(&sim.ec, 0x0104, None), 0x11);
+    assert_eq!(sim.readb(0x104), Ok(0x11));
+
+    // Bit 5 protects from host reads
+    xram(&sim.ec, 0x105D, Some(0x22));
+    assert_eq!(sim.readb(0x104), Err(HostError::Protected));
+    assert_eq!(sim.writeb(0x104, 0x5A), Ok(()));
+}
+
+#[test]
+fn io_window_protection() {
+    let mut sim = sim(true);
+    assert_eq!(sim.outb(0x110, 0x33), Ok(()));
+    assert_eq!(xram(&sim.ec, 0x0110, None), 0x33);
+
+    xram(&sim.ec, 0x105D, Some(0x12));
+    assert_eq!(sim.outb(0x110, 0x44), Err(HostError::Protected));
+    assert_eq!(sim.inb(0x110), Ok(0x33));
+}
@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::{Sim, xram};
+use ecsim::host::Notification;
+use ecsim::kbc::{self, STATUS_AUX, STATUS_CMD, STATUS_IBF, STATUS_OBF};
+
+fn sim() -> Sim {
+    Sim::from_rom(Vec::new())
+}
+
+#[test]
+fn mouse_data_sets_aux() {
+    let mut sim = sim();
+    // KBHICR: OBFKIE and OBFMIE
+    xram(&sim.ec, 0x1300, Some(0b11));
+
+    xram(&sim.ec, kbc::DATA_MOUSE as u16, Some(0xFA));
+    assert_eq!(sim.inb(0x64).unwrap() & (STATUS_AUX | STATUS_OBF), STATUS_AUX | STATUS_OBF);
+    assert_eq!(sim.notifications(), vec![Notification::Irq(12)]);
+    assert_eq!(sim.inb(0x60).unwrap(), 0xFA);
+    assert_eq!(sim.inb(0x64).unwrap() & STATUS_OBF, 0);
+
+    // Keyboard data clears AUX
+    xram(&sim.ec, kbc::DATA_KEYBOARD as u16, Some(0x1C));
+    assert_eq!(sim.inb(0x64).unwrap() & (STATUS_AUX | STATUS_OBF), STATUS_OBF);
+    assert_eq!(sim.notifications(), vec![Notification::Irq(1)]);
+    assert_eq!(sim.inb(0x60).unwrap(), 0x1C);
+}
+
+#[test]
+fn mouse_irq_needs_obfmie() {
+    let mut sim = sim();
+    xram(&sim.ec, 0x1300, Some(0b01));
+    xram(&sim.ec, kbc::DATA_MOUSE as u16, Some(0xFA));
+    assert!(sim.notifications().is_empty());
+    assert_ne!(sim.inb(0x64).unwrap() & STATUS_AUX, 0);
+}
+
+#[test]
+fn host_writes_to_aux_device() {
+    let mut sim = sim();
+
+    // Write to auxiliary device command, followed by its data
+    sim.outb(0x64, 0xD4).unwrap();
+    assert_eq!(sim.inb(0x64).unwrap() & (STATUS_CMD | STATUS_IBF), STATUS_CMD | STATUS_IBF);
+    assert_eq!(xram(&sim.ec, kbc::DATA_IN as u16, None), 0xD4);
+    assert_eq!(sim.inb(0x64).unwrap() & STATUS_IBF, 0);
+
+    sim.outb(0x60, 0xF4).unwrap();
+    assert_eq!(sim.inb(0x64).unwrap() & (STATUS_CMD | STATUS_IBF), STATUS_IBF);
+    assert_eq!(xram(&sim.ec, kbc::DATA_IN as u16, None), 0xF4);
+
+    // The mouse acknowledges through KBHIMDOR
+    xram(&sim.ec, kbc::DATA_MOUSE as u16, Some(0xFA));
+    assert_ne!(sim.inb(0x64).unwrap() & STATUS_AUX, 0);
+    assert_eq!(sim.inb(0x60).unwrap(), 0xFA);
+}
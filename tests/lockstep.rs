@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: MIT
+
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::Duration;
+
+use ecsim::{Ec, Runner};
+use ecsim::host::{Deterministic, Host, HostMessage};
+use ecsim::protocol::{Frame, OUTB, VERSION};
+
+/// Run the same POST code writes against idle firmware, pausing between them, and return the
+/// steps the EC saw them at
+fn replay(pause: Duration) -> Vec<u64> {
+    // LJMP 0x0003, then SJMP to itself
+    let rom = vec![0x02, 0x00, 0x03, 0x80, 0xFE];
+    let runner = Arc::new(Runner::new(Ec::from_rom(0x5570, 0x01, rom)));
+    let (sender, receiver) = mpsc::channel();
+    let host = Host::new(receiver, None, Some(Deterministic { latency: 100 }));
+    let run_thread = {
+        let runner = runner.clone();
+        thread::spawn(move || runner.run(Some(host)))
+    };
+
+    for value in 0..4u8 {
+        let mut payload = 0x80u16.to_le_bytes().to_vec();
+        payload.push(value);
+        sender.send(HostMessage::Frame {
+            client: 0,
+            frame: Frame { version: VERSION, kind: OUTB, id: value as u16, payload },
+        }).unwrap();
+        thread::sleep(pause);
+    }
+
+    while runner.lock().post_codes.len() < 4 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    runner.quit();
+    run_thread.join().unwrap();
+
+    let ec = runner.lock();
+    ec.post_codes.iter().map(|&(step, _, _)| step).collect()
+}
+
+#[test]
+fn pauses_do_not_change_steps() {
+    let steps = replay(Duration::from_millis(0));
+    assert_eq!(steps, vec![0, 100, 200, 300]);
+    assert_eq!(replay(Duration::from_millis(200)), steps);
+}
@@ -0,0 +1,71 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::{Sim, xram};
+use ecsim::host::HostError;
+use ecsim::pmc::{PMC_REGS, STATUS_CMD, STATUS_IBF, STATUS_OBF};
+use ecsim::superio::{LDN_PMC4, LDN_PMC5};
+
+/// Host ports of PMC3 to PMC5, as data and command
+const PORTS: [(usize, u16, u16); 3] = [(2, 0x6A, 0x6E), (3, 0x6B, 0x6F), (4, 0x6D, 0x71)];
+
+fn sim() -> Sim {
+    let sim = Sim::from_rom(Vec::new());
+    {
+        let mut superio = sim.ec.superio.lock().unwrap();
+        superio.set_ports(LDN_PMC4, Some((0x6B, 0x6F)));
+        superio.set_ports(LDN_PMC5, Some((0x6D, 0x71)));
+    }
+    sim
+}
+
+fn sts(sim: &Sim, channel: usize) -> u8 {
+    sim.ec.mcu.lock().unwrap().xram[PMC_REGS[channel].sts]
+}
+
+#[test]
+fn pmc4_and_pmc5_disabled_by_default() {
+    let mut sim = Sim::from_rom(Vec::new());
+    assert_eq!(sim.outb(0x6B, 0x12), Err(HostError::Unimplemented));
+    assert_eq!(sim.inb(0x71), Err(HostError::Unimplemented));
+}
+
+#[test]
+fn host_writes_reach_each_channel() {
+    let mut sim = sim();
+    for &(channel, data, command) in PORTS.iter() {
+        let regs = &PMC_REGS[channel];
+
+        sim.outb(data, 0x40 + channel as u8).unwrap();
+        assert_eq!(sts(&sim, channel) & (STATUS_IBF | STATUS_CMD), STATUS_IBF);
+        assert_eq!(xram(&sim.ec, regs.data_in as u16, None), 0x40 + channel as u8);
+        // Reading PMxDI clears IBF
+        assert_eq!(sts(&sim, channel) & STATUS_IBF, 0);
+
+        sim.outb(command, 0x80 + channel as u8).unwrap();
+        assert_eq!(sts(&sim, channel) & (STATUS_IBF | STATUS_CMD), STATUS_IBF | STATUS_CMD);
+        assert_eq!(sim.inb(command).unwrap() & STATUS_CMD, STATUS_CMD);
+        assert_eq!(xram(&sim.ec, regs.data_in as u16, None), 0x80 + channel as u8);
+    }
+}
+
+#[test]
+fn flags_are_per_channel() {
+    let mut sim = sim();
+
+    // IBF of PMC4 does not show in PMC3 or PMC5
+    sim.outb(0x6B, 0x12).unwrap();
+    assert_ne!(sim.inb(0x6F).unwrap() & STATUS_IBF, 0);
+    assert_eq!(sim.inb(0x6E).unwrap() & STATUS_IBF, 0);
+    assert_eq!(sim.inb(0x71).unwrap() & STATUS_IBF, 0);
+
+    // OBF of PMC5 does not show in PMC3 or PMC4
+    xram(&sim.ec, PMC_REGS[4].data_out as u16, Some(0x34));
+    assert_ne!(sim.inb(0x71).unwrap() & STATUS_OBF, 0);
+    assert_eq!(sim.inb(0x6E).unwrap() & STATUS_OBF, 0);
+    assert_eq!(sim.inb(0x6F).unwrap() & STATUS_OBF, 0);
+
+    // Reading the data clears only OBF of PMC5
+    assert_eq!(sim.inb(0x6D).unwrap(), 0x34);
+    assert_eq!(sim.inb(0x71).unwrap() & STATUS_OBF, 0);
+    assert_ne!(sim.inb(0x6F).unwrap() & STATUS_IBF, 0);
+}
@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::{Sim, xram};
+
+#[test]
+fn post_codes_are_latched_and_recorded() {
+    let mut sim = Sim::from_rom(Vec::new());
+
+    sim.ec.steps = 100;
+    sim.outb(0x80, 0x12).unwrap();
+    sim.ec.steps = 200;
+    sim.outb(0x81, 0x34).unwrap();
+
+    // P80HDR and P81HDR, with P80H81HS set until firmware clears it
+    assert_eq!(xram(&sim.ec, 0x2031, None), 0x12);
+    assert_eq!(xram(&sim.ec, 0x2032, None), 0x34);
+    assert_eq!(xram(&sim.ec, 0x2030, None) & 1, 1);
+    xram(&sim.ec, 0x2030, Some(1));
+    assert_eq!(xram(&sim.ec, 0x2030, None) & 1, 0);
+
+    let post_codes: Vec<_> = sim.ec.post_codes.iter().copied().collect();
+    assert_eq!(post_codes, vec![(100, 0x80, 0x12), (200, 0x81, 0x34)]);
+}
+
+#[test]
+fn post_code_history_is_capped() {
+    let mut sim = Sim::from_rom(Vec::new());
+    for i in 0..2000u32 {
+        sim.outb(0x80, i as u8).unwrap();
+    }
+    assert!(sim.ec.post_codes.len() < 2000);
+    assert_eq!(sim.ec.post_codes.back(), Some(&(0, 0x80, (1999 % 256) as u8)));
+    // The latch holds the last code
+    assert_eq!(xram(&sim.ec, 0x2031, None), (1999 % 256) as u8);
+}
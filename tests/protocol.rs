@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::Ec;
+use ecsim::protocol::{self, Frame, Outcome, PAYLOAD_MAX, execute};
+
+fn frame(length: usize) -> Frame {
+    Frame {
+        version: protocol::VERSION,
+        kind: protocol::MEMW,
+        id: 0x1234,
+        payload: (0..length).map(|i| i as u8).collect(),
+    }
+}
+
+#[test]
+fn largest_payload_round_trips() {
+    let bytes = frame(PAYLOAD_MAX).to_bytes().unwrap();
+    assert_eq!(bytes.len(), 6 + PAYLOAD_MAX);
+
+    let read = Frame::read(&mut bytes.as_slice()).unwrap();
+    assert_eq!(read.version, protocol::VERSION);
+    assert_eq!(read.kind, protocol::MEMW);
+    assert_eq!(read.id, 0x1234);
+    assert_eq!(read.payload, frame(PAYLOAD_MAX).payload);
+}
+
+#[test]
+fn oversize_payload_fails() {
+    assert!(frame(PAYLOAD_MAX + 1).to_bytes().is_err());
+}
+
+fn it5570() -> Ec {
+    Ec::from_rom(0x5570, 0x01, Vec::new())
+}
+
+fn request(kind: u8, payload: Vec<u8>) -> Frame {
+    Frame {
+        version: protocol::VERSION,
+        kind,
+        id: 7,
+        payload,
+    }
+}
+
+/// Execute a request that is answered immediately, returning the status and data
+fn done(ec: &mut Ec, frame: &Frame) -> (u8, Vec<u8>) {
+    match execute(ec, 0, frame) {
+        Outcome::Done(response) => {
+            assert_eq!(response.kind, frame.kind | protocol::RESPONSE);
+            assert_eq!(response.id, frame.id);
+            (response.payload[0], response.payload[1..].to_vec())
+        },
+        Outcome::Wait(_) => panic!("request is waiting"),
+    }
+}
+
+/// Add an operation to a batch payload
+fn op(payload: &mut Vec<u8>, kind: u8, data: &[u8]) {
+    payload.push(kind);
+    payload.push(data.len() as u8);
+    payload.extend_from_slice(data);
+}
+
+#[test]
+fn batch_stops_at_wait_and_bad_opcode() {
+    let mut ec = it5570();
+
+    // WAIT is only handled as a request of its own
+    let mut payload = Vec::new();
+    op(&mut payload, protocol::OUTB, &[0x2E, 0x00, 0x20]);
+    op(&mut payload, protocol::INB, &[0x2F, 0x00]);
+    op(&mut payload, protocol::WAIT, &[0x66, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00]);
+    op(&mut payload, protocol::INB, &[0x2F, 0x00]);
+    let (status, data) = done(&mut ec, &request(protocol::BATCH, payload));
+    assert_eq!(status, protocol::STATUS_BAD_REQUEST);
+    assert_eq!(data, [
+        protocol::STATUS_OK, 0,
+        protocol::STATUS_OK, 1, 0x55,
+        protocol::STATUS_BAD_REQUEST, 0,
+    ]);
+
+    let mut payload = Vec::new();
+    op(&mut payload, protocol::INB, &[0x2F, 0x00]);
+    op(&mut payload, 0x55, &[]);
+    let (status, data) = done(&mut ec, &request(protocol::BATCH, payload));
+    assert_eq!(status, protocol::STATUS_BAD_REQUEST);
+    assert_eq!(data, [protocol::STATUS_OK, 1, 0x55, protocol::STATUS_BAD_REQUEST, 0]);
+
+    // Truncated operation
+    let (status, _) = done(&mut ec, &request(protocol::BATCH, vec![protocol::INB, 2, 0x2F]));
+    assert_eq!(status, protocol::STATUS_BAD_REQUEST);
+}
+
+#[test]
+fn wait_completes_or_times_out() {
+    let mut ec = it5570();
+
+    // PMC1 IBF is clear, so the wait is answered immediately with the status read
+    let payload = vec![0x66, 0x00, 0x02, 0x00, 0x10, 0x00, 0x00, 0x00];
+    let (status, data) = done(&mut ec, &request(protocol::WAIT, payload));
+    assert_eq!(status, protocol::STATUS_OK);
+    assert_eq!(data.len(), 1);
+
+    // Waiting for IBF set only completes once the timeout in steps passes
+    let payload = vec![0x66, 0x00, 0x02, 0x02, 0x10, 0x00, 0x00, 0x00];
+    let wait = match execute(&mut ec, 3, &request(protocol::WAIT, payload)) {
+        Outcome::Wait(wait) => wait,
+        Outcome::Done(_) => panic!("wait completed without IBF"),
+    };
+    assert_eq!(wait.client, 3);
+    assert!(wait.check(&ec).is_none());
+    ec.steps += 0x10;
+    let response = wait.check(&ec).unwrap();
+    assert_eq!(response.id, 7);
+    assert_eq!(response.payload[0], protocol::STATUS_TIMEOUT);
+
+    let (status, _) = done(&mut ec, &request(protocol::WAIT, vec![0x66, 0x00]));
+    assert_eq!(status, protocol::STATUS_BAD_REQUEST);
+}
+
+#[test]
+fn oversize_response_fails() {
+    let mut ec = it5570();
+
+    // Each read returns 255 bytes, more than a frame holds in total
+    let mut payload = Vec::new();
+    for _ in 0..PAYLOAD_MAX / 255 + 1 {
+        op(&mut payload, protocol::MEMR, &[0x00, 0x00, 0xFE, 0xFF, 0xFF]);
+    }
+    let (status, data) = done(&mut ec, &request(protocol::BATCH, payload));
+    assert_eq!(status, protocol::STATUS_BAD_REQUEST);
+    assert!(data.is_empty());
+}
+
+#[test]
+fn bad_version_and_kind() {
+    let mut ec = it5570();
+
+    let mut frame = request(protocol::HELLO, Vec::new());
+    frame.version = protocol::VERSION + 1;
+    assert_eq!(done(&mut ec, &frame), (protocol::STATUS_BAD_VERSION, vec![protocol::VERSION]));
+
+    let (status, _) = done(&mut ec, &request(0x70, Vec::new()));
+    assert_eq!(status, protocol::STATUS_BAD_REQUEST);
+}
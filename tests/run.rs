@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: MIT
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ecsim::{Ec, Runner};
+
+fn runner() -> Arc<Runner> {
+    // LJMP 0x0003, then SJMP to itself
+    let rom = vec![0x02, 0x00, 0x03, 0x80, 0xFE];
+    Arc::new(Runner::new(Ec::from_rom(0x5570, 0x01, rom)))
+}
+
+#[test]
+fn command_completes_while_running() {
+    let runner = runner();
+    let run_thread = {
+        let runner = runner.clone();
+        thread::spawn(move || runner.run(None))
+    };
+
+    let start = Instant::now();
+    let first = runner.lock().steps;
+    let mut last = first;
+    for _ in 0..100 {
+        thread::sleep(Duration::from_millis(1));
+        // Like a REPL command, which has to get the lock while execution runs
+        last = runner.lock().steps;
+    }
+    let elapsed = start.elapsed();
+
+    runner.quit();
+    run_thread.join().unwrap();
+
+    assert!(last > first, "execution did not run between commands");
+    assert!(elapsed < Duration::from_secs(10), "commands took {:?}", elapsed);
+}
+
+#[test]
+fn runners_are_independent() {
+    let running = runner();
+    let paused = runner();
+    paused.set_running(false);
+
+    let run_threads: Vec<_> = [running.clone(), paused.clone()].iter().cloned().map(|runner| {
+        thread::spawn(move || runner.run(None))
+    }).collect();
+
+    thread::sleep(Duration::from_millis(50));
+    let paused_steps = paused.lock().steps;
+    assert!(running.lock().steps > 0, "running simulation did not execute");
+
+    // Stopping one simulation leaves the other running
+    paused.quit();
+    let steps = running.lock().steps;
+    thread::sleep(Duration::from_millis(50));
+    assert!(running.lock().steps > steps, "running simulation stopped with the other");
+    assert!(! running.quitting());
+
+    running.quit();
+    for run_thread in run_threads {
+        run_thread.join().unwrap();
+    }
+    assert_eq!(paused_steps, 0);
+}
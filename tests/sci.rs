@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::{Sim, xram};
+use ecsim::host::Notification;
+
+fn sim() -> Sim {
+    Sim::from_rom(Vec::new())
+}
+
+#[test]
+fn pmc_sci_is_recorded() {
+    let mut sim = sim();
+
+    // The host sends a command, and firmware answers through PM1DOSCI
+    sim.outb(0x66, 0x80).unwrap();
+    assert_eq!(xram(&sim.ec, 0x1504, None), 0x80);
+    sim.ec.steps = 42;
+    xram(&sim.ec, 0x1502, Some(0x12));
+
+    assert_eq!(sim.notifications(), vec![Notification::Sci]);
+    assert_eq!(sim.ec.scis.lock().unwrap().iter().cloned().collect::<Vec<_>>(), vec![(42, "pmc")]);
+    assert_eq!(sim.inb(0x62).unwrap(), 0x12);
+
+    // PM1DOSMI generates SMI instead
+    xram(&sim.ec, 0x1503, Some(0x34));
+    assert_eq!(sim.notifications(), vec![Notification::Smi]);
+    assert_eq!(sim.ec.scis.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn oldest_scis_are_dropped() {
+    let mut sim = sim();
+    for step in 0..1100 {
+        sim.ec.steps = step;
+        sim.ec.sci("pmc");
+    }
+
+    let scis = sim.ec.scis.lock().unwrap();
+    assert_eq!(scis.len(), 1024);
+    assert_eq!(scis.front(), Some(&(76, "pmc")));
+    assert_eq!(scis.back(), Some(&(1099, "pmc")));
+}
+
+#[test]
+fn sci_virtual_wire() {
+    let mut sim = sim();
+
+    // SCI# valid and low
+    xram(&sim.ec, 0x3206, Some(0x10));
+    assert_eq!(sim.notifications(), vec![Notification::VirtualWire(0x06, 0x10), Notification::Sci]);
+    assert_eq!(sim.ec.scis.lock().unwrap().back(), Some(&(0, "espi")));
+
+    // Writing the same levels again does not generate another SCI
+    xram(&sim.ec, 0x3206, Some(0x10));
+    assert!(sim.notifications().is_empty());
+
+    // Deassert, then assert again
+    xram(&sim.ec, 0x3206, Some(0x11));
+    assert_eq!(sim.notifications(), vec![Notification::VirtualWire(0x06, 0x11)]);
+    xram(&sim.ec, 0x3206, Some(0x10));
+    assert_eq!(sim.notifications(), vec![Notification::VirtualWire(0x06, 0x10), Notification::Sci]);
+    assert_eq!(sim.ec.scis.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn smi_virtual_wire() {
+    let mut sim = sim();
+
+    // SMI# valid and low, with SCI# not valid
+    xram(&sim.ec, 0x3206, Some(0x20));
+    assert_eq!(sim.notifications(), vec![Notification::VirtualWire(0x06, 0x20), Notification::Smi]);
+    assert!(sim.ec.scis.lock().unwrap().is_empty());
+
+    // A low level without its valid bit is not asserted
+    xram(&sim.ec, 0x3206, Some(0x02));
+    assert_eq!(sim.notifications(), vec![Notification::VirtualWire(0x06, 0x02)]);
+    xram(&sim.ec, 0x3206, Some(0x00));
+    assert!(sim.notifications().is_empty());
+}
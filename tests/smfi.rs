@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::{Sim, xram};
+use ecsim::host::HostError;
+
+/// Host address of the start of the 128 KiB internal flash
+const FLASH: u32 = 0xFFFE_0000;
+
+fn sim() -> Sim {
+    Sim::from_rom(Vec::new())
+}
+
+#[test]
+fn host_flash_writes_need_hostwa() {
+    let mut sim = sim();
+
+    assert_eq!(sim.writeb(FLASH + 0x100, 0x12), Err(HostError::Protected));
+    assert_eq!(sim.readb(FLASH + 0x100), Ok(0xFF));
+    assert_eq!(sim.ec.mcu.lock().unwrap().pmem[0x100], 0xFF);
+
+    // SMECCS HOSTWA
+    xram(&sim.ec, 0x1020, Some(0x20));
+    assert_eq!(sim.writeb(FLASH + 0x100, 0x12), Ok(()));
+    assert_eq!(sim.readb(FLASH + 0x100), Ok(0x12));
+    assert_eq!(sim.ec.mcu.lock().unwrap().pmem[0x100], 0x12);
+
+    // Clearing it protects the flash again
+    xram(&sim.ec, 0x1020, Some(0x00));
+    assert_eq!(sim.writeb(FLASH + 0x100, 0x34), Err(HostError::Protected));
+    assert_eq!(sim.ec.mcu.lock().unwrap().pmem[0x100], 0x12);
+}
+
+#[test]
+fn host_flash_follow_needs_hostwa() {
+    let mut sim = sim();
+    assert_eq!(sim.writeb(0xFFFF_FD00, 0x06), Err(HostError::Protected));
+
+    xram(&sim.ec, 0x1020, Some(0x20));
+    assert_eq!(sim.writeb(0xFFFF_FD00, 0x9F), Ok(()));
+    assert_eq!(sim.readb(0xFFFF_FD00), Ok(0xEF));
+    assert_eq!(sim.readb(0xFFFF_FE00), Ok(0xFF));
+}
+
+#[test]
+fn protect_region_blocks_host_writes_with_hostwa() {
+    let mut sim = sim();
+    xram(&sim.ec, 0x1020, Some(0x20));
+
+    // Protect region 0 blocks host writes to the first 4 KiB
+    xram(&sim.ec, 0x1070, Some(0x00));
+    xram(&sim.ec, 0x1071, Some(0x00));
+    xram(&sim.ec, 0x1072, Some(0x10));
+    assert_eq!(sim.writeb(FLASH + 0x100, 0x12), Err(HostError::Protected));
+    assert_eq!(sim.writeb(FLASH + 0x1000, 0x12), Ok(()));
+    // Reads are still allowed
+    assert_eq!(sim.readb(FLASH + 0x100), Ok(0xFF));
+}
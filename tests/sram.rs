@@ -0,0 +1,52 @@
+// SPDX-License-Identifier: MIT
+
+use area8051::{Addr, Mem};
+use ecsim::{Ec, xram};
+
+fn it5570() -> Ec {
+    let rom = (0..0x2000).map(|i| i as u8).collect();
+    Ec::from_rom(0x5570, 0x01, rom)
+}
+
+#[test]
+fn high_alias_writes_low_sram() {
+    let ec = it5570();
+    xram(&ec, 0x8123, Some(0x5A));
+    assert_eq!(xram(&ec, 0x0123, None), 0x5A);
+    assert_eq!(xram(&ec, 0x8123, None), 0x5A);
+}
+
+#[test]
+fn low_sram_writes_high_alias() {
+    let ec = it5570();
+    xram(&ec, 0x0FFF, Some(0xA5));
+    assert_eq!(xram(&ec, 0x8FFF, None), 0xA5);
+}
+
+#[test]
+fn sram_above_alias_is_separate() {
+    let ec = it5570();
+    xram(&ec, 0x0000, Some(0x11));
+    xram(&ec, 0x9000, Some(0x22));
+    assert_eq!(xram(&ec, 0x8000, None), 0x11);
+    assert_eq!(xram(&ec, 0x9000, None), 0x22);
+}
+
+#[test]
+fn scar_maps_code_to_alias() {
+    let ec = it5570();
+
+    // Map 0x1000 - 0x1FFF of code to SRAM, loading it by clearing bit 7 of SCAR0H
+    xram(&ec, 0x1040, Some(0x00));
+    xram(&ec, 0x1041, Some(0x10));
+    xram(&ec, 0x1042, Some(0x80));
+    xram(&ec, 0x1042, Some(0x00));
+    assert_eq!(xram(&ec, 0x8034, None), 0x34);
+    assert_eq!(xram(&ec, 0x0034, None), 0x34);
+
+    // Code fetches see SRAM writes through either address
+    xram(&ec, 0x0034, Some(0xC3));
+    assert_eq!(ec.load(Addr::PMem(0x1034)), 0xC3);
+    xram(&ec, 0x8035, Some(0xD4));
+    assert_eq!(ec.load(Addr::PMem(0x1035)), 0xD4);
+}
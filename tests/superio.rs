@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: MIT
+
+use ecsim::{Sim, xram};
+use ecsim::host::HostError;
+use ecsim::superio::LDN_PMC1;
+
+fn sim() -> Sim {
+    Sim::from_rom(Vec::new())
+}
+
+fn unlock(sim: &mut Sim) {
+    for &key in [0x87, 0x01, 0x55, 0x55].iter() {
+        sim.outb(0x2E, key).unwrap();
+    }
+}
+
+/// Write a SuperIO register through EC2I, as firmware does
+fn ec2i_write(sim: &Sim, reg: u8, value: u8) {
+    let ec = &sim.ec;
+    xram(ec, 0x1204, Some(0x01));
+    xram(ec, 0x1205, Some(0x01));
+    xram(ec, 0x1200, Some(0x00));
+    xram(ec, 0x1201, Some(reg));
+    xram(ec, 0x1200, Some(0x01));
+    xram(ec, 0x1201, Some(value));
+}
+
+/// Read a SuperIO register through EC2I, as firmware does
+fn ec2i_read(sim: &Sim, reg: u8) -> u8 {
+    let ec = &sim.ec;
+    xram(ec, 0x1204, Some(0x01));
+    xram(ec, 0x1205, Some(0x01));
+    xram(ec, 0x1200, Some(0x00));
+    xram(ec, 0x1201, Some(reg));
+    xram(ec, 0x1200, Some(0x01));
+    xram(ec, 0x1205, Some(0x03));
+    xram(ec, 0x1201, None)
+}
+
+#[test]
+fn locked_until_key_sequence() {
+    let mut sim = sim();
+    sim.outb(0x2E, 0x07).unwrap();
+    assert_eq!(sim.inb(0x2F), Err(HostError::Unimplemented));
+    sim.outb(0x2E, 0x07).unwrap();
+    sim.outb(0x2F, LDN_PMC1).unwrap_err();
+
+    unlock(&mut sim);
+    sim.outb(0x2E, 0x07).unwrap();
+    sim.outb(0x2F, LDN_PMC1).unwrap();
+    assert_eq!(sim.inb(0x2F), Ok(LDN_PMC1));
+}
+
+#[test]
+fn chip_id_without_key_sequence() {
+    // Probe like a host that has not entered configuration mode
+    let mut sim = sim();
+    sim.outb(0x2E, 0x20).unwrap();
+    assert_eq!(sim.inb(0x2F), Ok(0x55));
+    sim.outb(0x2E, 0x21).unwrap();
+    assert_eq!(sim.inb(0x2F), Ok(0x70));
+    sim.outb(0x2E, 0x22).unwrap();
+    assert_eq!(sim.inb(0x2F), Ok(0x01));
+
+    // The probe does not disturb a key sequence that follows
+    unlock(&mut sim);
+    sim.outb(0x2E, 0x07).unwrap();
+    assert!(sim.inb(0x2F).is_ok());
+}
+
+#[test]
+fn firmware_deactivates_pmc_for_host() {
+    let mut sim = sim();
+    assert!(sim.inb(0x66).is_ok());
+
+    ec2i_write(&sim, 0x07, LDN_PMC1);
+    ec2i_write(&sim, 0x30, 0x00);
+    assert_eq!(sim.inb(0x66), Err(HostError::Unimplemented));
+}
+
+#[test]
+fn firmware_sees_host_configuration() {
+    let mut sim = sim();
+    unlock(&mut sim);
+    for &(reg, value) in [(0x07, LDN_PMC1), (0x60, 0x02), (0x61, 0x00), (0x70, 0x05)].iter() {
+        sim.outb(0x2E, reg).unwrap();
+        sim.outb(0x2F, value).unwrap();
+    }
+
+    ec2i_write(&sim, 0x07, LDN_PMC1);
+    assert_eq!(ec2i_read(&sim, 0x60), 0x02);
+    assert_eq!(ec2i_read(&sim, 0x61), 0x00);
+    assert_eq!(ec2i_read(&sim, 0x70), 0x05);
+}